@@ -18,8 +18,12 @@ use routes::{
     version::handler as version,
     sqlite::handler as sqlite,
     sqlite::api_handler as sqlite_api,
+    sqlite::rooms_handler as sqlite_rooms,
+    sqlite::rooms_stats_handler as sqlite_rooms_stats,
     sqlite_test::handle as sqlite_test,
 };
+use routes::{sqlite_do::SqliteDO, stt::SttDO, study_do::StudyDO};
+use utils::do_router::forward;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -50,11 +54,26 @@ impl BaseTemplate {
 }
 
 pub mod utils {
+    pub mod do_router;
+    pub mod extract;
+    pub mod kv;
     pub mod scripture;
     pub mod turnstile;
     pub mod middleware;
     pub mod templates;
     pub mod sql_bindings;
+    pub mod sql_cache;
+    pub mod sql_console;
+    pub mod sql_backend;
+    pub mod sql_dump;
+    pub mod sql_json;
+    pub mod sql_export;
+    pub mod sql_migrations;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod sql_native;
+    pub mod sql_query;
+    pub mod sql_row;
+    pub mod sql_schema;
 }
 pub mod routes;
 
@@ -65,9 +84,7 @@ async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
     let (req, validation_state) = utils::middleware::validate_turnstile(req, &env, &ctx).await?;
     console_log!("Validation state: {}", validation_state.validation_message);
 
-    let router = Router::with_data(validation_state);
-    
-    let response = router
+    let router = Router::with_data(validation_state)
         .get_async("/", index)
         .get_async("/about", about)
         .get_async("/analytics", analytics)
@@ -77,10 +94,8 @@ async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
         .get_async("/websocket_do", websocket_do)
         .get_async("/websocket", websocket)
         .get_async("/study", study)
-        .get_async("/study_do", study_do)
         .get_async("/openai", openai)
         .get_async("/stt", stt)
-        .get_async("/stt/ws", stt_do)
         .get_async("/turnstile", turnstile::get_handler)
         .post_async("/turnstile", turnstile::post_handler)
         .get_async("/verify", verify::get_handler)
@@ -88,16 +103,16 @@ async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
         .get_async("/version", version)
         .get_async("/sqlite", sqlite)
         .get_async("/sqlite/test", sqlite_test)
-        .get_async("/sqlite/api/messages", sqlite_api)
-        .get_async("/sqlite/api/user/:id", sqlite_api)
-        .get_async("/sqlite/api/stats", sqlite_api)
-        .get_async("/sqlite/api/export", sqlite_api)
-        .get_async("/sqlite/api/sql-test", sqlite_api)
-        .post_async("/sqlite/api/message", sqlite_api)
-        .delete_async("/sqlite/api/old", sqlite_api)
-        .delete_async("/sqlite/api/messages", sqlite_api)
-        .run(req, env)
-        .await?;
+        .get_async("/sqlite/api/rooms", sqlite_rooms)
+        .get_async("/sqlite/api/rooms/stats", sqlite_rooms_stats)
+        .get_async("/sqlite/:room", sqlite);
+
+    // Durable Object APIs are forwarded pattern by pattern, as each object declares them
+    let router = forward(router, &SqliteDO::routes().merge(SqliteDO::admin_routes()).patterns(), sqlite_api);
+    let router = forward(router, &SttDO::routes().patterns(), stt_do);
+    let router = forward(router, &StudyDO::routes().patterns(), study_do);
+
+    let response = router.run(req, env).await?;
 
     if response.status_code() == 404 {
         return Response::from_html(
@@ -216,8 +216,8 @@ pub async fn data_handler(mut req: Request, ctx: RouteContext<ValidationState>)
     let _ = record_analytics(&ctx.env, data_point).await;
     
     // Return success response
-    Ok(Response::from_json(&AnalyticsResponse {
+    Response::from_json(&AnalyticsResponse {
         success: true,
         message: "Data point recorded".to_string(),
-    })?)
+    })
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use worker::*;
 use crate::utils::middleware::ValidationState;
+use crate::utils::extract::{Query, Rejection, Validate};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
@@ -11,51 +12,70 @@ struct ApiResponse {
     data: Option<Value>,
 }
 
+// Metrics the dashboard can ask for; the last two are reported with "all"
+const METRICS: &[&str] = &["all", "pageViews", "loadTime", "scrollDepth", "timeOnPage", "interactions"];
+
 // Query parameters for analytics API
 #[derive(Deserialize)]
 struct AnalyticsQuery {
-    period: Option<String>,
-    metric: Option<String>,
+    #[serde(default = "default_period")]
+    period: String,
+    #[serde(default = "default_metric")]
+    metric: String,
+}
+
+fn default_period() -> String {
+    "7d".to_string()
+}
+
+fn default_metric() -> String {
+    "all".to_string()
+}
+
+// Days covered by a period such as "7d"; the dashboard sends "last7d"
+fn period_days(period: &str) -> Option<u32> {
+    match period.strip_prefix("last").unwrap_or(period) {
+        "24h" => Some(1),
+        "7d" => Some(7),
+        "30d" => Some(30),
+        _ => None,
+    }
+}
+
+impl Validate for AnalyticsQuery {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        if period_days(&self.period).is_none() {
+            return Err(Rejection::field("period", format!("Unknown period: {} (expected 24h, 7d or 30d)", self.period)));
+        }
+        if !METRICS.contains(&self.metric.as_str()) {
+            return Err(Rejection::field("metric", format!("Unknown metric: {}", self.metric)));
+        }
+        Ok(())
+    }
 }
 
 pub async fn metrics_handler(req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
     // Extract query parameters
     let url = req.url()?;
-    let query_str = url.query().unwrap_or("");
-    
-    // Manually parse query parameters
-    let mut period = "7d".to_string();
-    let mut metric = "all".to_string();
-    
-    for param in query_str.split('&') {
-        if let Some((key, value)) = param.split_once('=') {
-            match key {
-                "period" => period = value.to_string(),
-                "metric" => metric = value.to_string(),
-                _ => {}
-            }
-        }
-    }
+    let query = match Query::<AnalyticsQuery>::extract(&url) {
+        Ok(Query(query)) => query,
+        Err(rejection) => return rejection.into_response(),
+    };
     
     // Query Analytics Engine
-    let analytics_data = query_analytics_data(&ctx.env, &period, &metric).await?;
+    let analytics_data = query_analytics_data(&ctx.env, &query.period, &query.metric).await?;
     
     // Return the analytics data
-    Ok(Response::from_json(&ApiResponse {
+    Response::from_json(&ApiResponse {
         success: true,
         message: "Analytics data retrieved successfully".to_string(),
         data: Some(analytics_data),
-    })?)
+    })
 }
 
 async fn query_analytics_data(env: &Env, period: &str, metric: &str) -> Result<Value> {
     // Get the number of days to look back
-    let days = match period {
-        "24h" => 1,
-        "7d" => 7,
-        "30d" => 30,
-        _ => 7, // Default to 7 days
-    };
+    let days = period_days(period).unwrap_or(7);
     
     // Only use real data - no simulation
     let result = match query_real_analytics(env, days, metric).await {
@@ -3,12 +3,20 @@ use crate::BaseTemplate;
 use crate::utils::middleware::ValidationState;
 use crate::utils::templates::render_template;
 use serde_json::json;
+use futures_util::future::join_all;
+use crate::routes::sqlite_do::{fetch_room, is_admin_route, is_valid_room, list_rooms, DEFAULT_ROOM};
 
 pub async fn handler(_req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
+    let api_base = match ctx.param("room") {
+        Some(room) if !is_valid_room(room) => return Response::error(format!("Invalid room name: {}", room), 400),
+        Some(room) => format!("/sqlite/{}/api", room),
+        None => "/sqlite/api".to_string(),
+    };
     let base = BaseTemplate::new(&ctx, "SQLite Demo", "SQLite in Durable Objects - Cloudflare Showcase").await?;
     
     let context = json!({
-        "base": base
+        "base": base,
+        "api_base": api_base
     });
     
     match render_template("sqlite.html", context) {
@@ -20,15 +28,27 @@ pub async fn handler(_req: Request, ctx: RouteContext<ValidationState>) -> Resul
     }
 }
 
+/// Forward a request to its room's Durable Object. Requests routed to
+/// `SqliteDO::admin_routes`, which run SQL, dump or import the database,
+/// migrate it, rewind it or change its retention policy, are forwarded for
+/// sessions that passed Turnstile only. Matching on the method as well as
+/// the path lets one path have both public and admin methods. The session
+/// id goes along, as the identity that owns the messages the caller posts.
 pub async fn api_handler(req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
     console_log!("SQLite API handler called: {} {}", req.method(), req.url()?.path());
     
-    let namespace = ctx.env.durable_object("SqliteDO")?;
-    // Use a consistent ID for the demo to maintain state across requests
-    let stub = namespace.id_from_name("sqlite-demo-instance")?.get_stub()?;
+    if !ctx.data.is_validated && is_admin_route(&req.method(), &req.path()) {
+        return Response::error("This endpoint requires a Turnstile-validated session", 403);
+    }
+    
+    // Each room is its own Durable Object; /sqlite/api is the default room
+    let room = ctx.param("room").map_or(DEFAULT_ROOM, String::as_str);
+    if !is_valid_room(room) {
+        return Response::error(format!("Invalid room name: {}", room), 400);
+    }
     
-    console_log!("Forwarding request to DO");
-    match stub.fetch_with_request(req).await {
+    console_log!("Forwarding request to DO for room '{}'", room);
+    match fetch_room(&ctx.env, room, &req, ctx.data.session.as_deref()).await {
         Ok(response) => {
             let status = response.status_code();
             console_log!("DO response status: {}", status);
@@ -44,4 +64,55 @@ pub async fn api_handler(req: Request, ctx: RouteContext<ValidationState>) -> Re
             })
         }
     }
-}
\ No newline at end of file
+}
+
+pub async fn rooms_handler(_req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
+    let rooms = list_rooms(&ctx.env).await?;
+    Response::from_json(&json!({ "rooms": rooms }))
+}
+
+/// Fetch `/stats` from every registered room concurrently and combine them.
+/// Rooms that fail are reported rather than failing the whole request.
+pub async fn rooms_stats_handler(_req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
+    let rooms = list_rooms(&ctx.env).await?;
+    
+    let results = join_all(rooms.iter().map(|room| async {
+        let url = format!("https://sqlite/sqlite/{}/api/stats", room.name);
+        let mut response = fetch_room(&ctx.env, &room.name, &Request::new(&url, Method::Get)?, None).await?;
+        response.json::<serde_json::Value>().await
+    })).await;
+    
+    let mut total_messages = 0;
+    let mut first_message_time: Option<i64> = None;
+    let mut last_message_time: Option<i64> = None;
+    let mut per_room = serde_json::Map::new();
+    let mut errors = serde_json::Map::new();
+    
+    for (room, result) in rooms.iter().zip(results) {
+        match result {
+            Ok(stats) => {
+                total_messages += stats["total_messages"].as_i64().unwrap_or(0);
+                if let Some(first) = stats["first_message_time"].as_i64() {
+                    first_message_time = Some(first_message_time.map_or(first, |t| t.min(first)));
+                }
+                if let Some(last) = stats["last_message_time"].as_i64() {
+                    last_message_time = Some(last_message_time.map_or(last, |t| t.max(last)));
+                }
+                per_room.insert(room.name.clone(), stats);
+            }
+            Err(e) => {
+                console_log!("Failed to fetch stats for room '{}': {:?}", room.name, e);
+                errors.insert(room.name.clone(), json!(e.to_string()));
+            }
+        }
+    }
+    
+    Response::from_json(&json!({
+        "room_count": rooms.len(),
+        "total_messages": total_messages,
+        "first_message_time": first_message_time,
+        "last_message_time": last_message_time,
+        "rooms": per_room,
+        "errors": errors
+    }))
+}
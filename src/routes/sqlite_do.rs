@@ -2,22 +2,494 @@ use worker::*;
 use worker::console_log;
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::utils::sql_bindings::{SqlStorageExt, Migration};
+use std::rc::Rc;
+use std::time::Duration;
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use crate::utils::sql_backend::{Rows, RunMeta, SqlBackend, SqlCursor};
+use crate::utils::sql_bindings::{reset_after, Bookmarks, Cursor, DoSql, DurableObjectState, SqlStorageExt};
+use crate::utils::do_router::{DoContext, DoRouter};
+use crate::utils::extract::{require_text, JsonBody, PathParam, Query, Rejection, Validate};
+use crate::utils::kv::{Key, Kv, Namespace};
+use crate::utils::sql_cache::{CachedSql, ProfiledCursor, StatementCache};
+use crate::utils::sql_console::{QueryLimits, QueryResult};
+use crate::utils::sql_export::{ExportChunks, ExportFormat};
+use crate::utils::sql_json::{json_each, Json, JsonPath};
+use crate::utils::sql_migrations::{Migration, MigrationStatus};
+use crate::utils::sql_query::{delete_from, insert_into, select, update, Cmp, Order};
+use crate::utils::sql_row::Row;
+use crate::utils::sql_schema::Schema;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct Message {
     id: Option<i64>,
     timestamp: i64,
     content: String,
     user_id: String,
+    #[serde(default)]
+    edited_at: Option<i64>,
+    #[serde(default)]
+    deleted_at: Option<i64>,
+    /// Free-form JSON object attached by the client when posting
+    #[serde(default)]
+    metadata: Option<Json<serde_json::Value>>,
+}
+
+/// A previous version of a message, recorded when it is edited
+#[derive(Serialize, Deserialize, Debug)]
+struct MessageEdit {
+    previous_content: String,
+    edited_at: i64,
+}
+
+/// Why a change to a single message was refused
+#[derive(Debug, PartialEq)]
+enum Refusal {
+    Missing,
+    OtherOwner,
+    NotYetDeleted,
+}
+
+impl Refusal {
+    fn into_response(self) -> Result<Response> {
+        match self {
+            Refusal::Missing => Response::error("Message not found", 404),
+            Refusal::OtherOwner => Response::error("Message belongs to another user", 403),
+            Refusal::NotYetDeleted => Response::error("Message is not deleted", 409),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Statistics {
+    total_messages: i64,
+    unique_users: i64,
+    first_message_time: Option<i64>,
+    last_message_time: Option<i64>,
+}
+
+/// Messages older than `max_age_ms`, and the oldest messages beyond
+/// `max_rows`, are pruned by the retention alarm. Either limit may be unset.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+struct RetentionPolicy {
+    max_age_ms: Option<i64>,
+    max_rows: Option<i64>,
+}
+
+impl RetentionPolicy {
+    fn is_enabled(&self) -> bool {
+        self.max_age_ms.is_some() || self.max_rows.is_some()
+    }
+}
+
+impl Validate for RetentionPolicy {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        if self.max_age_ms.is_some_and(|v| v <= 0) {
+            return Err(Rejection::field("max_age_ms", "max_age_ms must be positive"));
+        }
+        if self.max_rows.is_some_and(|v| v < 0) {
+            return Err(Rejection::field("max_rows", "max_rows must not be negative"));
+        }
+        Ok(())
+    }
+}
+
+/// What one pass of the retention alarm deleted. `pending` is set when the
+/// batch limit was reached and more messages are due to be pruned.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct RetentionRun {
+    ran_at: i64,
+    deleted_by_age: i64,
+    deleted_by_count: i64,
+    pending: bool,
+}
+
+/// A bookmark recorded just before a destructive change, to restore if the
+/// change was a mistake
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Checkpoint {
+    bookmark: String,
+    reason: String,
+    created_at: i64,
+}
+
+/// Where to restore the database to: a point in time or a bookmark
+#[derive(Deserialize, Debug)]
+struct RestoreRequest {
+    timestamp: Option<i64>,
+    bookmark: Option<String>,
+}
+
+impl RestoreRequest {
+    const MAX_BOOKMARK_LENGTH: usize = 256;
+}
+
+impl Validate for RestoreRequest {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        match (&self.timestamp, &self.bookmark) {
+            (Some(_), Some(_)) => Err(Rejection::field("bookmark", "Use either timestamp or bookmark, not both")),
+            (None, None) => Err(Rejection::field("timestamp", "A timestamp or bookmark is required")),
+            (None, Some(bookmark)) => require_text("bookmark", bookmark, Self::MAX_BOOKMARK_LENGTH),
+            (Some(_), None) => Ok(()),
+        }
+    }
+}
+
+/// Fail unless `timestamp` is within the recovery window before `now`
+fn check_recovery_time(field: &str, timestamp: i64, now: i64) -> std::result::Result<(), Rejection> {
+    if timestamp > now {
+        Err(Rejection::field(field, format!("{} cannot be in the future", field)))
+    } else if timestamp < now - RECOVERY_WINDOW_MS {
+        Err(Rejection::field(field, format!("{} must be within the last 30 days", field)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Keyset position in the (timestamp, id) ordering of messages
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+struct PageCursor {
+    timestamp: i64,
+    id: i64,
+}
+
+impl PageCursor {
+    /// Opaque, URL-safe token for clients to pass back
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.timestamp, self.id))
+    }
+    
+    fn decode(token: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+        let (timestamp, id) = decoded.split_once(':')?;
+        Some(Self {
+            timestamp: timestamp.parse().ok()?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+impl TryFrom<String> for PageCursor {
+    type Error = &'static str;
+    
+    fn try_from(token: String) -> std::result::Result<Self, Self::Error> {
+        Self::decode(&token).ok_or("not a valid cursor")
+    }
+}
+
+/// Which page of messages to read. Pages are always returned newest first.
+#[derive(Deserialize, Debug, Default)]
+struct PageRequest {
+    #[serde(default = "PageRequest::default_limit")]
+    limit: u32,
+    /// Only messages older than this position
+    before: Option<PageCursor>,
+    /// Only messages newer than this position
+    after: Option<PageCursor>,
+    /// Only messages whose metadata lists this tag in `tags`
+    tag: Option<String>,
+    #[serde(skip)]
+    user_id: Option<String>,
+}
+
+impl PageRequest {
+    const DEFAULT_LIMIT: u32 = 50;
+    const MAX_LIMIT: u32 = 500;
+    
+    fn default_limit() -> u32 {
+        Self::DEFAULT_LIMIT
+    }
+    
+    /// Read `limit`, `before`, `after` and `tag` from the query string
+    fn from_url(url: &Url) -> std::result::Result<Self, Rejection> {
+        let Query(mut page) = Query::<Self>::extract(url)?;
+        page.limit = page.limit.clamp(1, Self::MAX_LIMIT);
+        Ok(page)
+    }
+}
+
+impl Validate for PageRequest {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        if self.before.is_some() && self.after.is_some() {
+            return Err(Rejection::field("after", "Use either before or after, not both"));
+        }
+        Ok(())
+    }
+}
+
+/// A page of messages, newest first
+///
+/// `next_cursor` continues in the direction that was requested: pass it back
+/// as `before` after a `before` (or first) page, and as `after` after an
+/// `after` page. It is `null` when there are no more messages that way.
+#[derive(Serialize, Debug)]
+struct MessagePage {
+    messages: Vec<Message>,
+    next_cursor: Option<String>,
+}
+
+/// A change pushed to WebSocket subscribers, tagged by `type`
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MessageEvent {
+    /// A new message. `cursor` can be passed back as `since` when
+    /// reconnecting.
+    Created { message: Message, cursor: Option<String> },
+    Edited { message: Message },
+    Deleted { message: Message },
+    Restored { message: Message },
+    /// Messages removed for good by the retention policy
+    Pruned { ids: Vec<i64> },
+    /// Messages were replaced or removed in bulk; reload the list
+    Reset,
+    /// Catch-up is over and live updates follow. `complete` is false when
+    /// more was missed than is replayed, and the list should be reloaded.
+    Ready { cursor: Option<String>, complete: bool },
+}
+
+impl MessageEvent {
+    fn created(message: Message) -> Self {
+        let cursor = message.id.map(|id| PageCursor { timestamp: message.timestamp, id }.encode());
+        MessageEvent::Created { message, cursor }
+    }
+}
+
+/// Full-text search request: `q` plus `limit`/`offset` pagination over the
+/// ranked hits
+#[derive(Deserialize, Debug)]
+struct SearchRequest {
+    #[serde(rename = "q")]
+    query: String,
+    #[serde(default = "SearchRequest::default_limit")]
+    limit: u32,
+    #[serde(default)]
+    offset: u32,
+}
+
+impl SearchRequest {
+    const DEFAULT_LIMIT: u32 = 20;
+    const MAX_LIMIT: u32 = 100;
+    const MAX_QUERY_LENGTH: usize = 200;
+    
+    fn default_limit() -> u32 {
+        Self::DEFAULT_LIMIT
+    }
+    
+    fn from_url(url: &Url) -> std::result::Result<Self, Rejection> {
+        let Query(mut request) = Query::<Self>::extract(url)?;
+        request.limit = request.limit.clamp(1, Self::MAX_LIMIT);
+        Ok(request)
+    }
+}
+
+impl Validate for SearchRequest {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        require_text("q", &self.query, Self::MAX_QUERY_LENGTH)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SearchHit {
+    id: i64,
+    timestamp: i64,
+    content: String,
+    user_id: String,
+    /// HTML-escaped excerpt with matches wrapped in `<mark>`
+    snippet: String,
+    /// bm25 score; lower is a better match
+    rank: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct SearchResults {
+    query: String,
+    total: i64,
+    hits: Vec<SearchHit>,
+    /// Offset of the next page, or `null` on the last page
+    next_offset: Option<u32>,
+}
+
+/// Turn free text into an FTS5 query that matches every term. Terms are
+/// quoted so FTS5 operators and punctuation are treated as text; a trailing
+/// `*` is kept as a prefix match.
+fn fts_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input.split_whitespace()
+        .filter_map(|term| {
+            let (term, prefix) = match term.strip_suffix('*') {
+                Some(stem) => (stem, "*"),
+                None => (term, ""),
+            };
+            (!term.is_empty()).then(|| format!("\"{}\"{}", term.replace('"', "\"\""), prefix))
+        })
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Escape a snippet for HTML, turning the match markers the search query
+/// asks `snippet()` for into `<mark>` tags
+fn highlight(snippet: &str) -> String {
+    let mut out = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            '\u{E000}' => out.push_str("<mark>"),
+            '\u{E001}' => out.push_str("</mark>"),
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Optional filters for exporting messages. `since` is inclusive and
+/// `until` exclusive, both in milliseconds.
+#[derive(Deserialize, Debug, Default)]
+struct MessageFilter {
+    since: Option<i64>,
+    until: Option<i64>,
+    user_id: Option<String>,
+}
+
+impl Validate for MessageFilter {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        match (self.since, self.until) {
+            (Some(since), Some(until)) if until <= since => Err(Rejection::field("until", "until must be after since")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Longest message content accepted, in characters
+const MAX_MESSAGE_LENGTH: usize = 4000;
+/// Longest user id accepted, in characters
+const MAX_USER_ID_LENGTH: usize = 64;
+/// Largest metadata object accepted with a message, in bytes of JSON
+const MAX_METADATA_BYTES: usize = 4096;
+/// Most messages accepted by one bulk insert
+const MAX_BULK_INSERT: usize = 1000;
+/// Body limit for bulk inserts, which carry many messages
+const BULK_INSERT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+/// Longest console query accepted, in characters
+const MAX_QUERY_LENGTH: usize = 10_000;
+
+/// Content and author of a new message, as posted by clients
+#[derive(Deserialize, Debug)]
+struct MessageInput {
+    content: String,
+    user_id: String,
+}
+
+impl Validate for MessageInput {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        require_text("content", &self.content, MAX_MESSAGE_LENGTH)?;
+        require_text("user_id", &self.user_id, MAX_USER_ID_LENGTH)
+    }
+}
+
+/// A posted message, which may carry a JSON object of metadata
+#[derive(Deserialize, Debug)]
+struct NewMessage {
+    #[serde(flatten)]
+    input: MessageInput,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+impl Validate for NewMessage {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        self.input.validate()?;
+        match &self.metadata {
+            None | Some(serde_json::Value::Null) => Ok(()),
+            Some(metadata @ serde_json::Value::Object(_)) => {
+                if metadata.to_string().len() > MAX_METADATA_BYTES {
+                    return Err(Rejection::field("metadata", format!("metadata must be at most {} bytes of JSON", MAX_METADATA_BYTES)));
+                }
+                Ok(())
+            }
+            Some(_) => Err(Rejection::field("metadata", "metadata must be a JSON object")),
+        }
+    }
+}
+
+/// Where the API of the default room and of named rooms is mounted
+const API_PREFIXES: &[&str] = &["/sqlite/api", "/sqlite/:room/api"];
+/// Room served by the unprefixed `/sqlite/api` routes
+pub const DEFAULT_ROOM: &str = "sqlite-demo-instance";
+/// Internal instance that records which rooms exist. Its name is not a
+/// valid room, so clients cannot reach it.
+const REGISTRY_ROOM: &str = "_rooms";
+/// Header in which the Worker tells a room's object which room it serves
+pub const ROOM_HEADER: &str = "X-Sqlite-Room";
+/// Header in which the Worker passes the caller's Turnstile session. The
+/// Worker always replaces it, so the object can trust it as the author of
+/// the messages it posts and the only one allowed to change them.
+pub const SESSION_HEADER: &str = "X-Sqlite-Session";
+/// Key under which a room keeps its own name once registered
+const ROOM: Key<String> = Key::fixed("room");
+/// Registry entries, one per room, kept by the registry instance
+const ROOMS: Namespace<RoomEntry> = Namespace::new("room");
+
+/// Room names appear in URLs and are used as Durable Object names
+pub fn is_valid_room(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn room_stub(env: &Env, room: &str) -> Result<Stub> {
+    env.durable_object("SqliteDO")?.id_from_name(room)?.get_stub()
+}
+
+/// Send `req` to the object of `room`, naming the room in `ROOM_HEADER` so
+/// the object never has to work it out from the path. `/sqlite/api/...` can
+/// be the default room or, by pattern, a room called "api"; only the router
+/// that picked the pattern knows which. `session` replaces whatever
+/// `SESSION_HEADER` the client sent.
+pub async fn fetch_room(env: &Env, room: &str, req: &Request, session: Option<&str>) -> Result<Response> {
+    let mut req = req.clone_mut()?;
+    let headers = req.headers_mut()?;
+    headers.set(ROOM_HEADER, room)?;
+    headers.delete(SESSION_HEADER)?;
+    if let Some(session) = session {
+        headers.set(SESSION_HEADER, session)?;
+    }
+    room_stub(env, room)?.fetch_with_request(req).await
+}
+
+/// A room known to the registry
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RoomEntry {
+    pub name: String,
+    pub registered_at: i64,
+}
+
+impl Validate for RoomEntry {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        if !is_valid_room(&self.name) {
+            return Err(Rejection::field("name", format!("Invalid room name: {}", self.name)));
+        }
+        Ok(())
+    }
 }
 
 #[wasm_bindgen]
 pub struct SqliteDO {
     state: State,
-    #[allow(dead_code)]
+    /// The JS state object behind `state`, used to reset the object
+    object: DurableObjectState,
     env: Env,
     initialized: bool,
+    /// Set once this instance is known to be in the room registry
+    registered: bool,
+    /// Parsed statements and query profile, kept while the object is in memory
+    statements: Rc<StatementCache>,
+    routes: DoRouter<SqliteDO>,
+    registry_routes: DoRouter<SqliteDO>,
 }
 
 // Define migrations for the database
@@ -26,15 +498,492 @@ const MIGRATIONS: &[Migration] = &[
         version: 1,
         name: "create_messages_table",
         sql: include_str!("../sql/create_tables.sql"),
+        down: Some(include_str!("../sql/drop_tables.sql")),
     },
     Migration {
         version: 2,
         name: "create_indexes",
         sql: include_str!("../sql/create_indexes.sql"),
+        down: Some(include_str!("../sql/drop_indexes.sql")),
+    },
+    Migration {
+        version: 3,
+        name: "create_search_index",
+        sql: include_str!("../sql/create_search_index.sql"),
+        down: Some(include_str!("../sql/drop_search_index.sql")),
+    },
+    Migration {
+        version: 4,
+        name: "add_message_editing",
+        sql: include_str!("../sql/add_message_editing.sql"),
+        down: Some(include_str!("../sql/drop_message_editing.sql")),
+    },
+    Migration {
+        version: 5,
+        name: "create_retention",
+        sql: include_str!("../sql/create_retention.sql"),
+        down: Some(include_str!("../sql/drop_retention.sql")),
+    },
+    Migration {
+        version: 6,
+        name: "add_message_metadata",
+        sql: include_str!("../sql/add_message_metadata.sql"),
+        down: Some(include_str!("../sql/drop_message_metadata.sql")),
+    },
+    Migration {
+        version: 7,
+        name: "create_checkpoints",
+        sql: include_str!("../sql/create_checkpoints.sql"),
+        down: Some(include_str!("../sql/drop_checkpoints.sql")),
+    },
+    Migration {
+        version: 8,
+        name: "narrow_search_trigger",
+        sql: include_str!("../sql/narrow_search_trigger.sql"),
+        down: Some(include_str!("../sql/widen_search_trigger.sql")),
+    },
+    Migration {
+        version: 9,
+        name: "add_message_author",
+        sql: include_str!("../sql/add_message_author.sql"),
+        down: Some(include_str!("../sql/drop_message_author.sql")),
     },
 ];
 
+/// Most messages one retention pass deletes, so an alarm never holds the
+/// object for long
+const RETENTION_BATCH_SIZE: i64 = 500;
+/// Most missed messages replayed to a reconnecting subscriber
+const CATCH_UP_LIMIT: u32 = 500;
+/// Rows returned by a console query unless the request asks for fewer or more
+const QUERY_DEFAULT_ROWS: usize = 100;
+const QUERY_MAX_ROWS: usize = 1000;
+/// Rows a console query may read, as SQLite counts them, unless the request
+/// asks for fewer or more
+const QUERY_DEFAULT_ROWS_READ: u64 = 100_000;
+const QUERY_MAX_ROWS_READ: u64 = 1_000_000;
+/// Retention passes kept in the run log
+const RETENTION_RUNS_KEPT: i64 = 100;
+/// Alarm delay after a pass that left messages pending
+const RETENTION_CATCH_UP: Duration = Duration::from_secs(1);
+/// Alarm delay between regular retention passes
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How far back point-in-time recovery reaches
+const RECOVERY_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+/// Checkpoints kept in the log
+const CHECKPOINTS_KEPT: i64 = 100;
+/// Time given to a restore response before the object resets to apply it
+const RESTORE_RESET_DELAY: Duration = Duration::from_millis(250);
+
+/// Queries on the messages table, independent of the SQL backend so they
+/// can run against an embedded SQLite in tests
+struct MessageStore<'a, B: SqlBackend> {
+    sql: &'a B,
+}
+
+impl<'a, B: SqlBackend> MessageStore<'a, B> {
+    fn new(sql: &'a B) -> Self {
+        Self { sql }
+    }
+    
+    fn add(&self, content: String, user_id: String, author: Option<&str>, timestamp: i64) -> Result<Message> {
+        self.add_with_metadata(content, user_id, None, author, timestamp)
+    }
+    
+    /// Insert a message, storing `metadata` as JSON text and `author` as the
+    /// session that may later change it
+    fn add_with_metadata(&self, content: String, user_id: String, metadata: Option<serde_json::Value>, author: Option<&str>, timestamp: i64) -> Result<Message> {
+        let metadata = metadata.filter(|m| !m.is_null()).map(Json);
+        let id = insert_into("messages")
+            .value("timestamp", timestamp)
+            .value("content", content.as_str())
+            .value("user_id", user_id.as_str())
+            .value("metadata", metadata.as_ref())
+            .value("author", author)
+            .returning(&["id"])
+            .prepare(self.sql)
+            .first::<(i64,)>()?
+            .map(|(id,)| id);
+        
+        Ok(Message {
+            id,
+            timestamp,
+            content,
+            user_id,
+            edited_at: None,
+            deleted_at: None,
+            metadata,
+        })
+    }
+    
+    /// Look up a message, including soft-deleted ones
+    fn get(&self, id: i64) -> Result<Option<Message>> {
+        select(&[]).from("messages")
+            .where_eq("id", id)
+            .prepare(self.sql)
+            .first::<Message>()
+    }
+    
+    /// Fetch a message for a change by the session `author`, refusing if it
+    /// is missing or was posted by another session, or by none. The
+    /// displayed `user_id` is chosen by clients, so it proves nothing.
+    /// `deleted` says which state the message must be in.
+    fn owned(&self, id: i64, author: &str, deleted: bool) -> Result<std::result::Result<Message, Refusal>> {
+        let Some(message) = self.get(id)? else {
+            return Ok(Err(Refusal::Missing));
+        };
+        let stored = select(&["author"]).from("messages")
+            .where_eq("id", id)
+            .prepare(self.sql)
+            .first::<(Option<String>,)>()?;
+        if !matches!(stored, Some((Some(ref stored),)) if stored == author) {
+            return Ok(Err(Refusal::OtherOwner));
+        }
+        match (message.deleted_at.is_some(), deleted) {
+            (true, false) => Ok(Err(Refusal::Missing)),
+            (false, true) => Ok(Err(Refusal::NotYetDeleted)),
+            _ => Ok(Ok(message)),
+        }
+    }
+    
+    /// Replace a message's content, keeping the previous version in its history
+    fn edit(&self, id: i64, author: &str, content: String, now: i64) -> Result<std::result::Result<Message, Refusal>> {
+        self.sql.transaction(|sql| {
+            let store = MessageStore::new(sql);
+            let message = match store.owned(id, author, false)? {
+                Ok(message) => message,
+                Err(refusal) => return Ok(Err(refusal)),
+            };
+            
+            insert_into("message_edits")
+                .value("message_id", id)
+                .value("previous_content", message.content.as_str())
+                .value("edited_at", now)
+                .prepare(sql)
+                .run()?;
+            update("messages")
+                .set("content", content.as_str())
+                .set("edited_at", now)
+                .where_eq("id", id)
+                .prepare(sql)
+                .run()?;
+            
+            Ok(Ok(Message { content, edited_at: Some(now), ..message }))
+        })
+    }
+    
+    /// Soft-delete a message. It disappears from listings, search, exports
+    /// and statistics until restored.
+    fn soft_delete(&self, id: i64, author: &str, now: i64) -> Result<std::result::Result<Message, Refusal>> {
+        self.set_deleted_at(id, author, false, Some(now))
+    }
+    
+    fn restore(&self, id: i64, author: &str) -> Result<std::result::Result<Message, Refusal>> {
+        self.set_deleted_at(id, author, true, None)
+    }
+    
+    fn set_deleted_at(&self, id: i64, author: &str, deleted: bool, deleted_at: Option<i64>) -> Result<std::result::Result<Message, Refusal>> {
+        self.sql.transaction(|sql| {
+            let message = match MessageStore::new(sql).owned(id, author, deleted)? {
+                Ok(message) => message,
+                Err(refusal) => return Ok(Err(refusal)),
+            };
+            
+            update("messages")
+                .set("deleted_at", deleted_at)
+                .where_eq("id", id)
+                .prepare(sql)
+                .run()?;
+            
+            Ok(Ok(Message { deleted_at, ..message }))
+        })
+    }
+    
+    /// Previous versions of a message, oldest first
+    fn history(&self, id: i64) -> Result<Vec<MessageEdit>> {
+        select(&["previous_content", "edited_at"]).from("message_edits")
+            .where_eq("message_id", id)
+            .order_by("edited_at", Order::Asc)
+            .order_by("id", Order::Asc)
+            .prepare(self.sql)
+            .all::<MessageEdit>()
+    }
+    
+    /// Read one page of messages using keyset pagination on (timestamp, id),
+    /// which stays on the timestamp and user_id indexes however deep the page
+    fn page(&self, request: &PageRequest) -> Result<MessagePage> {
+        let mut query = select(&[]).from("messages").where_null("deleted_at");
+        if let Some(user_id) = &request.user_id {
+            query = query.where_eq("user_id", user_id);
+        }
+        if let Some(tag) = &request.tag {
+            let tags = json_each("messages.metadata", &JsonPath::root().key("tags"));
+            query = query.where_sql(&format!("EXISTS (SELECT 1 FROM {} WHERE value = ?)", tags), [tag]);
+        }
+        let order = match (request.before, request.after) {
+            (_, Some(after)) => {
+                query = query.where_sql("(timestamp, id) > (?, ?)", [after.timestamp, after.id]);
+                Order::Asc
+            }
+            (Some(before), None) => {
+                query = query.where_sql("(timestamp, id) < (?, ?)", [before.timestamp, before.id]);
+                Order::Desc
+            }
+            (None, None) => Order::Desc,
+        };
+        
+        // One extra row tells us whether there is another page
+        let mut messages = query
+            .order_by("timestamp", order)
+            .order_by("id", order)
+            .limit(request.limit as i64 + 1)
+            .prepare(self.sql)
+            .all::<Message>()?;
+        let has_more = messages.len() > request.limit as usize;
+        messages.truncate(request.limit as usize);
+        
+        let next_cursor = messages.last()
+            .filter(|_| has_more)
+            .and_then(|m| Some(PageCursor { timestamp: m.timestamp, id: m.id? }.encode()));
+        if request.after.is_some() {
+            messages.reverse();
+        }
+        
+        Ok(MessagePage { messages, next_cursor })
+    }
+    
+    /// Ranked full-text search over message content
+    fn search(&self, request: &SearchRequest) -> Result<SearchResults> {
+        let Some(query) = fts_query(&request.query) else {
+            return Ok(SearchResults {
+                query: request.query.clone(),
+                total: 0,
+                hits: Vec::new(),
+                next_offset: None,
+            });
+        };
+        
+        let (total,) = self.sql.prepare(
+            "SELECT COUNT(*) FROM messages_fts JOIN messages m ON m.id = messages_fts.rowid WHERE messages_fts MATCH ? AND m.deleted_at IS NULL"
+        )
+            .bind_value(query.as_str())
+            .first::<(i64,)>()?
+            .unwrap_or_default();
+        
+        let hits = self.sql.prepare(include_str!("../sql/search_messages.sql"))
+            .bind_named("query", query.as_str())
+            .bind_named("limit", request.limit as i64)
+            .bind_named("offset", request.offset as i64)
+            .all::<SearchHit>()?
+            .into_iter()
+            .map(|hit| SearchHit { snippet: highlight(&hit.snippet), ..hit })
+            .collect::<Vec<_>>();
+        
+        let next = request.offset as i64 + hits.len() as i64;
+        Ok(SearchResults {
+            query: request.query.clone(),
+            total,
+            next_offset: (next < total).then_some(next as u32),
+            hits,
+        })
+    }
+    
+    /// Stream messages oldest first, for exports
+    fn export(&self, filter: &MessageFilter) -> Result<Rows<B::Cursor, Row>> {
+        let mut query = select(&["id", "timestamp", "content", "user_id", "edited_at"]).from("messages")
+            .where_null("deleted_at");
+        if let Some(since) = filter.since {
+            query = query.where_cmp("timestamp", Cmp::Ge, since);
+        }
+        if let Some(until) = filter.until {
+            query = query.where_cmp("timestamp", Cmp::Lt, until);
+        }
+        if let Some(user_id) = &filter.user_id {
+            query = query.where_eq("user_id", user_id);
+        }
+        
+        query.order_by("timestamp", Order::Asc)
+            .order_by("id", Order::Asc)
+            .prepare(self.sql)
+            .rows()
+    }
+    
+    fn delete_all(&self) -> Result<RunMeta> {
+        self.sql.prepare(include_str!("../sql/delete_messages.sql")).run()
+    }
+    
+    /// Insert messages atomically. Composes with an enclosing transaction,
+    /// in which case a failure rolls back only these inserts.
+    fn insert_many(&self, messages: Vec<(String, String)>, author: Option<&str>, timestamp: i64) -> Result<Vec<Message>> {
+        self.sql.transaction(|sql| {
+            let store = MessageStore::new(sql);
+            messages.into_iter()
+                .map(|(content, user_id)| store.add(content, user_id, author, timestamp))
+                .collect()
+        })
+    }
+    
+    /// Replay what a subscriber missed since `since`: earlier messages edited
+    /// or deleted at or after its timestamp, then messages created after it,
+    /// oldest first. Restores are not replayed, as they leave no timestamp.
+    /// The flag is false when either part had more than `limit` rows.
+    fn catch_up(&self, since: PageCursor, limit: u32) -> Result<(Vec<MessageEvent>, bool)> {
+        let mut changed = select(&[]).from("messages")
+            .where_sql("(timestamp, id) <= (?, ?)", [since.timestamp, since.id])
+            .where_sql("edited_at >= ? OR deleted_at >= ?", [since.timestamp, since.timestamp])
+            .order_by("id", Order::Asc)
+            .limit(limit as i64 + 1)
+            .prepare(self.sql)
+            .all::<Message>()?;
+        let mut created = self.page(&PageRequest { limit, after: Some(since), ..Default::default() })?;
+        
+        let complete = changed.len() <= limit as usize && created.next_cursor.is_none();
+        changed.truncate(limit as usize);
+        created.messages.reverse();
+        
+        let events = changed.into_iter()
+            .map(|message| match message.deleted_at {
+                Some(_) => MessageEvent::Deleted { message },
+                None => MessageEvent::Edited { message },
+            })
+            .chain(created.messages.into_iter().map(MessageEvent::created))
+            .collect();
+        Ok((events, complete))
+    }
+    
+    fn statistics(&self) -> Result<Statistics> {
+        Ok(self.sql.execute(include_str!("../sql/get_statistics.sql"))?
+            .first::<Statistics>()?
+            .unwrap_or_default())
+    }
+    
+    fn retention_policy(&self) -> Result<RetentionPolicy> {
+        Ok(self.sql.execute("SELECT max_age_ms, max_rows FROM retention_policy WHERE id = 1")?
+            .first::<RetentionPolicy>()?
+            .unwrap_or_default())
+    }
+    
+    fn set_retention_policy(&self, policy: RetentionPolicy, now: i64) -> Result<()> {
+        self.sql.prepare(
+            "INSERT INTO retention_policy (id, max_age_ms, max_rows, updated_at) VALUES (1, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                max_age_ms = excluded.max_age_ms,
+                max_rows = excluded.max_rows,
+                updated_at = excluded.updated_at"
+        )
+            .bind_value(policy.max_age_ms)
+            .bind_value(policy.max_rows)
+            .bind_value(now)
+            .run()?;
+        Ok(())
+    }
+    
+    /// Most recent retention passes, newest first
+    fn retention_runs(&self, limit: i64) -> Result<Vec<RetentionRun>> {
+        select(&["ran_at", "deleted_by_age", "deleted_by_count", "pending"]).from("retention_runs")
+            .order_by("id", Order::Desc)
+            .limit(limit)
+            .prepare(self.sql)
+            .all::<RetentionRun>()
+    }
+    
+    /// Delete up to `batch_size` messages the policy no longer keeps, oldest
+    /// first, and record the pass in the run log
+    fn prune(&self, policy: RetentionPolicy, now: i64, batch_size: i64) -> Result<(RetentionRun, Vec<i64>)> {
+        self.sql.transaction(|sql| {
+            let store = MessageStore::new(sql);
+            let mut run = RetentionRun { ran_at: now, ..Default::default() };
+            let mut ids = Vec::new();
+            
+            if let Some(max_age_ms) = policy.max_age_ms {
+                let cutoff = now.saturating_sub(max_age_ms);
+                ids = store.delete_oldest(cutoff, batch_size)?;
+                run.deleted_by_age = ids.len() as i64;
+                run.pending = store.count_before(cutoff)? > 0;
+            }
+            
+            if let Some(max_rows) = policy.max_rows {
+                let excess = (store.count_before(i64::MAX)? - max_rows).max(0);
+                let budget = batch_size - run.deleted_by_age;
+                let by_count = store.delete_oldest(i64::MAX, excess.min(budget))?;
+                run.deleted_by_count = by_count.len() as i64;
+                run.pending |= excess > run.deleted_by_count;
+                ids.extend(by_count);
+            }
+            
+            insert_into("retention_runs")
+                .value("ran_at", run.ran_at)
+                .value("deleted_by_age", run.deleted_by_age)
+                .value("deleted_by_count", run.deleted_by_count)
+                .value("pending", run.pending)
+                .prepare(sql)
+                .run()?;
+            let kept = select(&["id"]).from("retention_runs").order_by("id", Order::Desc).limit(RETENTION_RUNS_KEPT);
+            delete_from("retention_runs").where_not_in("id", kept).prepare(sql).run()?;
+            
+            Ok((run, ids))
+        })
+    }
+    
+    /// Log a bookmark taken before a destructive change
+    fn record_checkpoint(&self, bookmark: &str, reason: &str, now: i64) -> Result<()> {
+        insert_into("checkpoints")
+            .value("bookmark", bookmark)
+            .value("reason", reason)
+            .value("created_at", now)
+            .prepare(self.sql)
+            .run()?;
+        let kept = select(&["id"]).from("checkpoints").order_by("id", Order::Desc).limit(CHECKPOINTS_KEPT);
+        delete_from("checkpoints").where_not_in("id", kept).prepare(self.sql).run()?;
+        Ok(())
+    }
+    
+    /// Most recent checkpoints, newest first
+    fn checkpoints(&self, limit: i64) -> Result<Vec<Checkpoint>> {
+        select(&["bookmark", "reason", "created_at"]).from("checkpoints")
+            .order_by("id", Order::Desc)
+            .limit(limit)
+            .prepare(self.sql)
+            .all::<Checkpoint>()
+    }
+    
+    /// Messages of any state with a timestamp before `cutoff`
+    fn count_before(&self, cutoff: i64) -> Result<i64> {
+        Ok(select(&["COUNT(*)"]).from("messages")
+            .where_cmp("timestamp", Cmp::Lt, cutoff)
+            .prepare(self.sql)
+            .first::<(i64,)>()?
+            .map_or(0, |(count,)| count))
+    }
+    
+    /// Delete the `limit` oldest messages before `cutoff` with their edit
+    /// history, returning their ids
+    fn delete_oldest(&self, cutoff: i64, limit: i64) -> Result<Vec<i64>> {
+        if limit <= 0 {
+            return Ok(Vec::new());
+        }
+        
+        let oldest = select(&["id"]).from("messages")
+            .where_cmp("timestamp", Cmp::Lt, cutoff)
+            .order_by("timestamp", Order::Asc)
+            .order_by("id", Order::Asc)
+            .limit(limit);
+        delete_from("message_edits").where_in("message_id", oldest.clone()).prepare(self.sql).run()?;
+        let deleted = delete_from("messages")
+            .where_in("id", oldest)
+            .returning(&["id"])
+            .prepare(self.sql)
+            .all::<(i64,)>()?;
+        Ok(deleted.into_iter().map(|(id,)| id).collect())
+    }
+}
+
 impl SqliteDO {
+    /// SQL handle that shares this object's statement cache
+    fn sql(&self) -> Result<CachedSql<DoSql>> {
+        Ok(CachedSql::new(self.state.storage().sql()?, self.statements.clone()))
+    }
+    
     async fn init_database(&mut self) -> Result<()> {
         let storage = self.state.storage();
         
@@ -66,128 +1015,309 @@ impl SqliteDO {
         }
     }
     
-    async fn add_message(&self, content: String, user_id: String) -> Result<Message> {
+    async fn add_message(&self, content: String, user_id: String, metadata: Option<serde_json::Value>, author: Option<&str>) -> Result<Message> {
         console_log!("Adding message: {} from user: {}", content, user_id);
         
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
-        let timestamp = Date::now().as_millis() as i64;
+        let sql = self.sql()?;
+        let message = MessageStore::new(&sql).add_with_metadata(content, user_id, metadata, author, Date::now().as_millis() as i64)?;
         
-        // Use the new PreparedStatement API for safer queries
-        let id = sql.prepare("INSERT INTO messages (timestamp, content, user_id) VALUES (?, ?, ?) RETURNING id")
-            .bind_value(timestamp)
-            .bind_value(content.as_str())
-            .bind_value(user_id.as_str())
-            .first::<serde_json::Value>()?
-            .and_then(|row| row.get("id").and_then(|v| v.as_i64()));
+        console_log!("Message inserted with id: {:?}", message.id);
+        self.broadcast(&MessageEvent::created(message.clone()));
+        Ok(message)
+    }
+    
+    async fn get_messages(&self, request: &PageRequest) -> Result<MessagePage> {
+        console_log!("Getting messages page: {:?}", request);
         
-        console_log!("Message inserted with id: {:?}", id);
+        let sql = self.sql()?;
+        let page = MessageStore::new(&sql).page(request)?;
         
-        Ok(Message {
-            id,
-            timestamp,
-            content,
-            user_id,
-        })
+        console_log!("Found {} messages", page.messages.len());
+        Ok(page)
     }
     
-    async fn get_recent_messages(&self, limit: u32) -> Result<Vec<Message>> {
-        console_log!("Getting recent messages, limit: {}", limit);
-        
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
-        
-        // Use PreparedStatement for parameterized query
-        let messages = sql.prepare("SELECT * FROM messages ORDER BY timestamp DESC LIMIT ?")
-            .bind_value(limit as i32)
-            .all::<Message>()?;
+    async fn search_messages(&self, request: &SearchRequest) -> Result<SearchResults> {
+        console_log!("Searching messages: {:?}", request);
+        let sql = self.sql()?;
+        MessageStore::new(&sql).search(request)
+    }
+    
+    async fn export_messages(&self, filter: &MessageFilter) -> Result<Rows<ProfiledCursor<Cursor>, Row>> {
+        console_log!("Exporting messages with filter: {:?}", filter);
+        let sql = self.sql()?;
+        MessageStore::new(&sql).export(filter)
+    }
+    
+    /// Respond with a changed message and tell subscribers, or explain why
+    /// it was left alone
+    fn changed_message(&self, outcome: std::result::Result<Message, Refusal>, event: fn(Message) -> MessageEvent) -> Result<Response> {
+        match outcome {
+            Ok(message) => {
+                let response = Response::from_json(&message)?;
+                self.broadcast(&event(message));
+                Ok(response)
+            }
+            Err(refusal) => refusal.into_response(),
+        }
+    }
+    
+    /// Record the current bookmark before a destructive change. Failing to
+    /// get one, as in local development, does not stop the change.
+    async fn checkpoint(&self, reason: &str) -> Option<String> {
+        let bookmark = match Bookmarks::new(&self.state.storage()).current().await {
+            Ok(bookmark) => bookmark,
+            Err(e) => {
+                console_log!("No checkpoint before {}: {}", reason, e);
+                return None;
+            }
+        };
+        let recorded = self.sql()
+            .and_then(|sql| MessageStore::new(&sql).record_checkpoint(&bookmark, reason, Date::now().as_millis() as i64));
+        if let Err(e) = recorded {
+            console_log!("Failed to log checkpoint {}: {}", bookmark, e);
+        }
+        Some(bookmark)
+    }
+    
+    async fn bookmarks(&self, at: Option<i64>) -> Result<serde_json::Value> {
+        let bookmarks = Bookmarks::new(&self.state.storage());
+        let at = match at {
+            Some(timestamp) => Some(serde_json::json!({
+                "timestamp": timestamp,
+                "bookmark": bookmarks.for_time(timestamp).await?
+            })),
+            None => None,
+        };
+        let sql = self.sql()?;
         
-        console_log!("Found {} messages", messages.len());
-        Ok(messages)
+        Ok(serde_json::json!({
+            "current": bookmarks.current().await?,
+            "at": at,
+            "checkpoints": MessageStore::new(&sql).checkpoints(20)?
+        }))
     }
     
-    async fn get_user_messages(&self, user_id: &str) -> Result<Vec<Message>> {
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
+    /// Schedule a restore and reset the object so that it is applied.
+    /// Returns the bookmark restored to and one that undoes the restore.
+    async fn restore(&self, request: RestoreRequest) -> Result<(String, String)> {
+        let bookmarks = Bookmarks::new(&self.state.storage());
+        let bookmark = match (request.bookmark, request.timestamp) {
+            (Some(bookmark), _) => bookmark,
+            (None, Some(timestamp)) => bookmarks.for_time(timestamp).await?,
+            (None, None) => return Err(Error::RustError("A timestamp or bookmark is required".into())),
+        };
         
-        // Use PreparedStatement for safe parameter binding
-        sql.prepare("SELECT * FROM messages WHERE user_id = ? ORDER BY timestamp DESC")
-            .bind_value(user_id)
-            .all::<Message>()
+        let undo = bookmarks.restore_on_next_session(&bookmark).await?;
+        console_log!("Restore to {} scheduled, undo with {}", bookmark, undo);
+        reset_after(&self.state, &self.object, RESTORE_RESET_DELAY, "Restoring to a bookmark");
+        Ok((bookmark, undo))
     }
     
     async fn delete_messages(&self) -> Result<u64> {
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
+        let sql = self.sql()?;
+        let meta = MessageStore::new(&sql).delete_all()?;
+        console_log!("DELETE read {} rows, wrote {} rows", meta.rows_read, meta.rows_written);
+        self.broadcast(&MessageEvent::Reset);
+        Ok(meta.changes)
+    }
+    
+    async fn bulk_insert_messages(&self, messages: Vec<(String, String)>, author: Option<&str>) -> Result<usize> {
+        let sql = self.sql()?;
+        let inserted = MessageStore::new(&sql).insert_many(messages, author, Date::now().as_millis() as i64)?;
+        let count = inserted.len();
+        for message in inserted {
+            self.broadcast(&MessageEvent::created(message));
+        }
+        Ok(count)
+    }
+    
+    async fn export_database(&self) -> Result<String> {
+        console_log!("Exporting database as SQL statements");
+        let sql = self.sql()?;
+        sql.dump_sql()
+    }
+    
+    async fn export_database_image(&self) -> Result<Vec<u8>> {
+        console_log!("Exporting database as binary image");
+        let sql = self.state.storage().sql()?;
+        sql.dump_db()
+    }
+    
+    async fn import_database(&self, script: &str) -> Result<u64> {
+        console_log!("Importing SQL dump of {} bytes", script.len());
+        self.checkpoint("import").await;
+        let sql = self.sql()?;
+        let rows_written = sql.import_sql(script)?;
+        self.broadcast(&MessageEvent::Reset);
+        Ok(rows_written)
+    }
+    
+    
+    async fn get_statistics(&self) -> Result<Statistics> {
+        console_log!("Getting statistics");
+        
+        let sql = self.sql()?;
+        let stats = MessageStore::new(&sql).statistics()?;
+        console_log!("Stats row: {:?}", stats);
         
-        // Use execute method for non-parameterized queries
-        sql.execute("DELETE FROM messages")?;
-        Ok(0) // SQLite DO doesn't provide row count directly
+        Ok(stats)
     }
     
-    async fn bulk_insert_messages(&self, messages: Vec<(String, String)>) -> Result<usize> {
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
+    /// Accept a hibernatable WebSocket subscription. With a `since` cursor
+    /// the missed changes are sent first; a `ready` event always follows.
+    /// The socket is accepted only once catch-up has been read, so a failed
+    /// query leaves no subscriber registered.
+    async fn subscribe(&self, since: Option<PageCursor>) -> Result<Response> {
+        let sql = self.sql()?;
+        let store = MessageStore::new(&sql);
+        let (mut events, cursor, complete) = match since {
+            Some(since) => {
+                let (events, complete) = store.catch_up(since, CATCH_UP_LIMIT)?;
+                let cursor = events.iter()
+                    .rev()
+                    .find_map(|event| match event {
+                        MessageEvent::Created { cursor: Some(created), .. } => Some(created.clone()),
+                        _ => None,
+                    })
+                    .unwrap_or_else(|| since.encode());
+                (events, Some(cursor), complete)
+            }
+            None => {
+                let newest = store.page(&PageRequest { limit: 1, ..Default::default() })?;
+                let cursor = newest.messages.first()
+                    .and_then(|m| Some(PageCursor { timestamp: m.timestamp, id: m.id? }.encode()));
+                (Vec::new(), cursor, true)
+            }
+        };
+        events.push(MessageEvent::Ready { cursor, complete });
         
-        // Demonstrate transaction usage
-        sql.transaction(|sql| {
-            let mut count = 0;
-            let timestamp = Date::now().as_millis() as i64;
-            
-            for (content, user_id) in messages {
-                sql.prepare("INSERT INTO messages (timestamp, content, user_id) VALUES (?, ?, ?)")
-                    .bind_value(timestamp)
-                    .bind_value(content.as_str())
-                    .bind_value(user_id.as_str())
-                    .run()?;
-                count += 1;
+        let pair = WebSocketPair::new()?;
+        self.state.accept_web_socket(&pair.server);
+        if let Err(e) = events.iter().try_for_each(|event| pair.server.send(event)) {
+            let _ = pair.server.close(Some(1011), Some("Catch-up failed"));
+            return Err(e);
+        }
+        console_log!("Subscriber connected, {} open", self.state.get_websockets().len());
+        
+        Response::from_websocket(pair.client)
+    }
+    
+    /// Send an event to every subscriber. A socket that fails is left for
+    /// the runtime to close.
+    fn broadcast(&self, event: &MessageEvent) {
+        for ws in self.state.get_websockets() {
+            if let Err(e) = ws.send(event) {
+                console_log!("Failed to send to subscriber: {:?}", e);
             }
-            
-            Ok(count)
-        })
+        }
     }
     
-    async fn export_database(&self) -> Result<Vec<u8>> {
-        console_log!("Exporting database as binary dump");
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
+    /// Record this instance in the room registry the first time it serves a
+    /// request
+    async fn register_room(&mut self, room: &str) -> Result<()> {
+        let mut kv = Kv::new(self.state.storage());
+        if kv.get(&ROOM).await?.is_none() {
+            console_log!("Registering room '{}'", room);
+            
+            let entry = RoomEntry {
+                name: room.to_string(),
+                registered_at: Date::now().as_millis() as i64,
+            };
+            let mut init = RequestInit::new();
+            init.with_method(Method::Post)
+                .with_body(Some(serde_json::to_string(&entry)?.into()));
+            let request = Request::new_with_init(&format!("https://sqlite/sqlite/{}/api/rooms", REGISTRY_ROOM), &init)?;
+            
+            let response = fetch_room(&self.env, REGISTRY_ROOM, &request, None).await?;
+            if response.status_code() != 200 {
+                return Err(Error::RustError(format!("Room registry returned {}", response.status_code())));
+            }
+            kv.put(&ROOM, &room.to_string()).await?;
+        }
         
-        // Use the new dump_db method for a complete binary database dump
-        sql.dump_db()
+        self.registered = true;
+        Ok(())
     }
     
+    async fn get_retention(&self) -> Result<serde_json::Value> {
+        let sql = self.sql()?;
+        let store = MessageStore::new(&sql);
+        let next_run = self.state.storage().get_alarm().await?;
+        
+        Ok(serde_json::json!({
+            "policy": store.retention_policy()?,
+            "next_run": next_run,
+            "runs": store.retention_runs(20)?
+        }))
+    }
     
-    async fn get_statistics(&self) -> Result<serde_json::Value> {
-        console_log!("Getting statistics");
+    /// Save the policy and start or stop the retention alarm to match
+    async fn set_retention(&self, policy: RetentionPolicy) -> Result<()> {
+        console_log!("Setting retention policy: {:?}", policy);
         
-        let storage = self.state.storage();
-        let sql = storage.sql()?;
+        let sql = self.sql()?;
+        MessageStore::new(&sql).set_retention_policy(policy, Date::now().as_millis() as i64)?;
         
-        match sql.exec(include_str!("../sql/get_statistics.sql")) {
-            Ok(cursor) => {
-                let stats = cursor.toArray();
-                console_log!("Stats query returned {} rows", stats.length());
-                
-                if stats.length() > 0 {
-                    let row = stats.get(0);
-                    console_log!("Stats row: {:?}", row);
-                    Ok(serde_wasm_bindgen::from_value(row)?)
-                } else {
-                    Ok(serde_json::json!({
-                        "total_messages": 0,
-                        "unique_users": 0,
-                        "first_message_time": null,
-                        "last_message_time": null
-                    }))
-                }
-            },
-            Err(e) => {
-                console_log!("Failed to get statistics: {:?}", e);
-                Err(Error::JsError(format!("Failed to query stats: {:?}", e)))
-            }
+        if policy.is_enabled() {
+            self.state.storage().set_alarm(RETENTION_CATCH_UP).await
+        } else {
+            self.state.storage().delete_alarm().await
         }
     }
     
+    /// Run one bounded retention pass and schedule the next one
+    async fn prune_messages(&self) -> Result<Option<RetentionRun>> {
+        let sql = self.sql()?;
+        let store = MessageStore::new(&sql);
+        let policy = store.retention_policy()?;
+        if !policy.is_enabled() {
+            return Ok(None);
+        }
+        
+        let (run, ids) = store.prune(policy, Date::now().as_millis() as i64, RETENTION_BATCH_SIZE)?;
+        if !ids.is_empty() {
+            self.broadcast(&MessageEvent::Pruned { ids });
+        }
+        console_log!("Retention pruned {} by age and {} by count (pending: {})", run.deleted_by_age, run.deleted_by_count, run.pending);
+        
+        let delay = if run.pending { RETENTION_CATCH_UP } else { RETENTION_INTERVAL };
+        self.state.storage().set_alarm(delay).await?;
+        Ok(Some(run))
+    }
+    
+    async fn get_migration_status(&self) -> Result<Vec<MigrationStatus>> {
+        let sql = self.sql()?;
+        sql.migration_status(MIGRATIONS)
+    }
+    
+    async fn migrate_to(&self, version: i32) -> Result<Vec<MigrationStatus>> {
+        console_log!("Migrating schema to version {}", version);
+        
+        let sql = self.sql()?;
+        sql.migrate_to(MIGRATIONS, version)?;
+        sql.migration_status(MIGRATIONS)
+    }
+    
+    async fn run_query(&self, query: &str, limits: QueryLimits) -> Result<QueryResult> {
+        console_log!("Running console query: {}", query);
+        let sql = self.sql()?;
+        crate::utils::sql_console::run_read_only(&sql, query, limits)
+    }
+    
+    /// Query profile of this object since profiling was last turned on
+    fn profile(&self) -> serde_json::Value {
+        serde_json::json!({
+            "enabled": self.statements.profiling(),
+            "cached_statements": self.statements.len(),
+            "queries": self.statements.report()
+        })
+    }
+    
+    async fn get_schema(&self) -> Result<Schema> {
+        let sql = self.sql()?;
+        sql.schema()
+    }
+    
     async fn sql_test(&self) -> Result<Response> {
         console_log!("Running SQL test");
         
@@ -201,10 +1331,9 @@ impl SqliteDO {
                 console_log!("Successfully accessed SQL object");
                 
                 // Try a simple query to verify it works
-                match sql.exec(include_str!("../sql/simple_test.sql")) {
+                match sql.execute(include_str!("../sql/simple_test.sql")) {
                     Ok(cursor) => {
-                        let result = cursor.toArray();
-                        if result.length() > 0 {
+                        if cursor.raw().next().is_some() {
                             Response::from_json(&serde_json::json!({
                                 "success": true,
                                 "message": "SQL access successful",
@@ -234,159 +1363,1153 @@ impl SqliteDO {
     }
 }
 
+impl SqliteDO {
+    /// Routes served by every room to any client. The Worker forwards these
+    /// patterns along with the admin ones, so an endpoint only needs adding
+    /// here.
+    pub fn routes() -> DoRouter<SqliteDO> {
+        DoRouter::new()
+            .prefixed(API_PREFIXES)
+            .post("/message", endpoints::post_message)
+            .patch("/message/:id", endpoints::edit_message)
+            .delete("/message/:id", endpoints::delete_message)
+            .post("/message/:id/restore", endpoints::restore_message)
+            .get("/message/:id/history", endpoints::message_history)
+            .get("/messages", endpoints::list_messages)
+            .delete("/messages", endpoints::delete_messages)
+            .get("/messages/export", endpoints::export_messages)
+            .post("/bulk-insert", endpoints::bulk_insert)
+            .get("/user/:id", endpoints::user_messages)
+            .get("/subscribe", endpoints::subscribe)
+            .get("/search", endpoints::search)
+            .get("/stats", endpoints::stats)
+            .get("/retention", endpoints::get_retention)
+            .delete("/old", endpoints::prune_messages)
+            .get("/sql-test", endpoints::sql_test)
+    }
+    
+    /// Routes that expose SQL or the whole database, or rewrite it, including
+    /// the retention policy that the alarm keeps applying. The Worker
+    /// forwards these only for Turnstile-validated sessions.
+    pub fn admin_routes() -> DoRouter<SqliteDO> {
+        DoRouter::new()
+            .prefixed(API_PREFIXES)
+            .get("/export", endpoints::export_database)
+            .post("/import", endpoints::import_database)
+            .get("/migrations", endpoints::migrations)
+            .post("/migrations", endpoints::migrate)
+            .post("/query", endpoints::run_query)
+            .get("/bookmarks", endpoints::bookmarks)
+            .post("/restore", endpoints::restore)
+            .get("/profile", endpoints::profile)
+            .post("/profile", endpoints::set_profiling)
+            .delete("/profile", endpoints::reset_profile)
+            .get("/schema", endpoints::schema)
+            .put("/retention", endpoints::set_retention)
+    }
+    
+    /// The registry instance only lists and records rooms
+    fn registry_routes() -> DoRouter<SqliteDO> {
+        DoRouter::new()
+            .prefixed(API_PREFIXES)
+            .get("/rooms", endpoints::registered_rooms)
+            .post("/rooms", endpoints::record_room)
+    }
+}
+
+thread_local! {
+    /// `SqliteDO::admin_routes`, built once for the Worker to check requests
+    /// against rather than for every request
+    static ADMIN_ROUTES: DoRouter<SqliteDO> = SqliteDO::admin_routes();
+}
+
+/// Whether `method` on `path` reaches one of `SqliteDO::admin_routes`
+pub fn is_admin_route(method: &Method, path: &str) -> bool {
+    ADMIN_ROUTES.with(|routes| routes.handles(method, path))
+}
+
 #[durable_object]
 impl DurableObject for SqliteDO {
     fn new(state: State, env: Env) -> Self {
+        let (state, object) = DurableObjectState::split(state);
         Self {
             state,
+            object,
             env,
             initialized: false,
+            registered: false,
+            statements: StatementCache::new(),
+            routes: Self::routes().merge(Self::admin_routes()),
+            registry_routes: Self::registry_routes(),
         }
     }
     
-    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
         if !self.initialized {
             self.init_database().await?;
         }
         
-        let url = req.url()?;
-        let path = url.path();
+        let path = req.path();
         console_log!("SQLite DO received request: {} {}", req.method(), path);
-        console_log!("Full URL: {}", url.to_string());
         
-        // Strip the /sqlite/api prefix from the path
-        let api_path = path.strip_prefix("/sqlite/api").unwrap_or(path);
-        console_log!("Stripped path: '{}' -> '{}'", path, api_path);
+        let room = match req.headers().get(ROOM_HEADER)? {
+            Some(room) if room == REGISTRY_ROOM || is_valid_room(&room) => room,
+            _ => return Response::error("Requests must name their room", 400),
+        };
+        let env = self.env.clone();
+        if room == REGISTRY_ROOM {
+            return self.registry_routes.run(&*self, req, env).await;
+        }
+        if !self.registered {
+            self.register_room(&room).await?;
+        }
+        
+        self.routes.run(&*self, req, env).await
+    }
+    
+    async fn alarm(&mut self) -> Result<Response> {
+        if !self.initialized {
+            self.init_database().await?;
+        }
+        
+        self.prune_messages().await?;
+        Response::ok("")
+    }
+    
+    async fn websocket_message(&mut self, _ws: WebSocket, _message: WebSocketIncomingMessage) -> Result<()> {
+        // Subscriptions are push-only
+        Ok(())
+    }
+    
+    async fn websocket_close(&mut self, _ws: WebSocket, _code: usize, _reason: String, _was_clean: bool) -> Result<()> {
+        console_log!("Subscriber disconnected");
+        Ok(())
+    }
+    
+    async fn websocket_error(&mut self, _ws: WebSocket, error: Error) -> Result<()> {
+        console_log!("Subscriber error: {:?}", error);
+        Ok(())
+    }
+}
+
+/// Handlers for `SqliteDO::routes`, with the Durable Object as route data
+mod endpoints {
+    use super::*;
+    
+    /// New content for an edit
+    #[derive(Deserialize)]
+    struct EditRequest {
+        content: String,
+    }
+    
+    impl Validate for EditRequest {
+        fn validate(&self) -> std::result::Result<(), Rejection> {
+            require_text("content", &self.content, MAX_MESSAGE_LENGTH)
+        }
+    }
+    
+    /// The Turnstile session the Worker forwarded, if the caller has one
+    fn session(req: &Request) -> Result<Option<String>> {
+        req.headers().get(SESSION_HEADER)
+    }
+    
+    /// The session changing a message. Messages belong to the session that
+    /// posted them, so callers without one cannot change any.
+    fn required_session(req: &Request) -> Result<std::result::Result<String, Response>> {
+        match session(req)? {
+            Some(session) => Ok(Ok(session)),
+            None => Response::error("Changing a message requires a Turnstile-validated session", 403).map(Err),
+        }
+    }
+    
+    fn message_id(ctx: &DoContext<'_, SqliteDO>) -> std::result::Result<i64, Rejection> {
+        PathParam::extract("id", ctx.param("id").map_or("", String::as_str))
+    }
+    
+    pub async fn post_message(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let body = match JsonBody::<NewMessage>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let message = ctx.data.add_message(body.input.content, body.input.user_id, body.metadata, session(&req)?.as_deref()).await?;
+        
+        console_log!("Message stored with id: {:?}", message.id);
+        
+        Response::from_json(&message)
+    }
+    
+    pub async fn list_messages(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
+        
+        let request = match PageRequest::from_url(&url) {
+            Ok(request) => request,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let page = ctx.data.get_messages(&request).await?;
+        Response::from_json(&page)
+    }
+    
+    pub async fn subscribe(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
+        
+        if !req.headers().get("Upgrade")?.is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+            return Response::error("Expected Upgrade: websocket", 426);
+        }
+        
+        #[derive(Deserialize)]
+        struct SubscribeRequest {
+            since: Option<PageCursor>,
+        }
+        
+        impl Validate for SubscribeRequest {}
         
-        // Now also log what we're matching against
-        console_log!("Attempting to match: method={:?}, api_path='{}'", req.method(), api_path);
+        match Query::<SubscribeRequest>::extract(&url) {
+            Ok(Query(request)) => ctx.data.subscribe(request.since).await,
+            Err(rejection) => rejection.into_response(),
+        }
+    }
+    
+    pub async fn search(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
         
-        match (req.method(), api_path) {
-            (Method::Post, "/message") => {
-                #[derive(Deserialize)]
-                struct PostMessage {
-                    content: String,
-                    user_id: String,
+        let request = match SearchRequest::from_url(&url) {
+            Ok(request) => request,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let results = ctx.data.search_messages(&request).await?;
+        Response::from_json(&results)
+    }
+    
+    pub async fn export_messages(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
+        
+        #[derive(Deserialize)]
+        struct FormatRequest {
+            format: Option<String>,
+        }
+        
+        impl Validate for FormatRequest {
+            fn validate(&self) -> std::result::Result<(), Rejection> {
+                match self.format.as_deref() {
+                    Some(format) if ExportFormat::parse(format).is_none() => Err(Rejection::field(
+                        "format",
+                        format!("Unknown export format: {} (expected csv, ndjson or json)", format),
+                    )),
+                    _ => Ok(()),
                 }
-                
-                let body: PostMessage = req.json().await.map_err(|e| Error::RustError(format!("Failed to parse JSON: {}", e)))?;
-                let message = self.add_message(body.content, body.user_id).await?;
-                
-                console_log!("Message stored with id: {:?}", message.id);
-                
-                Response::from_json(&message)
             }
-            
-            (Method::Get, "/messages") => {
-                let query_string = url.query().unwrap_or_default();
-                let query_params: std::collections::HashMap<String, String> = query_string
-                    .split('&')
-                    .filter(|s| !s.is_empty())
-                    .filter_map(|pair| {
-                        let mut parts = pair.split('=');
-                        match (parts.next(), parts.next()) {
-                            (Some(key), Some(value)) => Some((key.to_string(), value.to_string())),
-                            _ => None,
-                        }
-                    })
-                    .collect();
-                
-                let limit = query_params.get("limit")
-                    .and_then(|l| l.parse().ok())
-                    .unwrap_or(50);
-                    
-                let messages = self.get_recent_messages(limit).await?;
-                Response::from_json(&messages)
-            }
-            
-            (Method::Get, user_path) if user_path.starts_with("/user/") => {
-                let user_id = user_path.trim_start_matches("/user/");
-                let messages = self.get_user_messages(user_id).await?;
-                Response::from_json(&messages)
+        }
+        
+        let (format, filter) = match (Query::<FormatRequest>::extract(&url), Query::<MessageFilter>::extract(&url)) {
+            (Ok(Query(request)), Ok(Query(filter))) => {
+                (request.format.as_deref().and_then(ExportFormat::parse).unwrap_or(ExportFormat::Csv), filter)
             }
-            
-            (Method::Delete, "/messages") => {
-                console_log!("Processing DELETE /messages request");
-                console_log!("Deleting all messages");
-                let deleted = self.delete_messages().await?;
-                console_log!("Deleted {} messages", deleted);
-                
-                Response::from_json(&serde_json::json!({
-                    "deleted": deleted,
-                    "message": "All messages deleted successfully"
-                }))
+            (Err(rejection), _) | (_, Err(rejection)) => return rejection.into_response(),
+        };
+        
+        let rows = ctx.data.export_messages(&filter).await?;
+        let columns = rows.columns().to_vec();
+        let chunks = ExportChunks::new(format, columns, rows);
+        
+        let mut response = Response::from_stream(futures_util::stream::iter(chunks))?;
+        response.headers_mut().set("Content-Type", format.content_type())?;
+        response.headers_mut().set("Content-Disposition", &format!("attachment; filename=\"messages.{}\"", format.extension()))?;
+        response.headers_mut().set("Cache-Control", "no-store")?;
+        Ok(response)
+    }
+    
+    pub async fn delete_messages(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        console_log!("Processing DELETE /messages request");
+        let checkpoint = ctx.data.checkpoint("delete_messages").await;
+        console_log!("Deleting all messages");
+        let deleted = ctx.data.delete_messages().await?;
+        console_log!("Deleted {} messages", deleted);
+        
+        Response::from_json(&serde_json::json!({
+            "deleted": deleted,
+            "checkpoint": checkpoint,
+            "message": "All messages deleted successfully"
+        }))
+    }
+    
+    pub async fn bookmarks(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        #[derive(Deserialize)]
+        struct BookmarkQuery {
+            at: Option<i64>,
+        }
+        
+        impl Validate for BookmarkQuery {}
+        
+        let query = match Query::<BookmarkQuery>::extract(&req.url()?) {
+            Ok(Query(query)) => query,
+            Err(rejection) => return rejection.into_response(),
+        };
+        if let Some(at) = query.at {
+            if let Err(rejection) = check_recovery_time("at", at, Date::now().as_millis() as i64) {
+                return rejection.into_response();
             }
-            
-            (Method::Delete, "/old") => {
-                // Remove this endpoint - we should just use the /messages endpoint
-                // For backward compatibility, we'll just redirect to /messages
-                console_log!("DELETE /old route is deprecated, redirecting to /messages");
-                let deleted = self.delete_messages().await?;
-                console_log!("Deleted {} messages", deleted);
-                
-                Response::from_json(&serde_json::json!({
-                    "deleted": deleted,
-                    "message": "All messages deleted successfully"
-                }))
+        }
+        
+        Response::from_json(&ctx.data.bookmarks(query.at).await?)
+    }
+    
+    pub async fn restore(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let body = match JsonBody::<RestoreRequest>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        if let Some(timestamp) = body.timestamp {
+            if let Err(rejection) = check_recovery_time("timestamp", timestamp, Date::now().as_millis() as i64) {
+                return rejection.into_response();
             }
-            
-            (Method::Post, "/bulk-insert") => {
-                #[derive(Deserialize)]
-                struct BulkInsertRequest {
-                    messages: Vec<MessageInput>,
-                }
-                
-                #[derive(Deserialize)]
-                struct MessageInput {
-                    content: String,
-                    user_id: String,
+        }
+        
+        match ctx.data.restore(body).await {
+            Ok((bookmark, undo)) => Response::from_json(&serde_json::json!({
+                "success": true,
+                "bookmark": bookmark,
+                "undo_bookmark": undo,
+                "message": "Restore scheduled; the room restarts to apply it"
+            })).map(|r| r.with_status(202)),
+            Err(e) => Response::from_json(&serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            })).map(|r| r.with_status(500)),
+        }
+    }
+    
+    pub async fn get_retention(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let retention = ctx.data.get_retention().await?;
+        Response::from_json(&retention)
+    }
+    
+    pub async fn set_retention(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let policy = match JsonBody::<RetentionPolicy>::extract(&mut req).await {
+            Ok(JsonBody(policy)) => policy,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        ctx.data.set_retention(policy).await?;
+        let retention = ctx.data.get_retention().await?;
+        Response::from_json(&retention)
+    }
+    
+    pub async fn prune_messages(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        // Prune by the retention policy now rather than waiting for the alarm
+        match ctx.data.prune_messages().await? {
+            Some(run) => Response::from_json(&run),
+            None => Response::error("No retention policy is set", 409),
+        }
+    }
+    
+    pub async fn bulk_insert(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        #[derive(Deserialize)]
+        struct BulkInsertRequest {
+            messages: Vec<MessageInput>,
+        }
+        
+        impl Validate for BulkInsertRequest {
+            fn validate(&self) -> std::result::Result<(), Rejection> {
+                if self.messages.len() > MAX_BULK_INSERT {
+                    return Err(Rejection::field("messages", format!("At most {} messages can be inserted at once", MAX_BULK_INSERT)));
                 }
-                
-                let body: BulkInsertRequest = req.json().await
-                    .map_err(|e| Error::RustError(format!("Failed to parse JSON: {}", e)))?;
-                
-                let messages: Vec<(String, String)> = body.messages
-                    .into_iter()
-                    .map(|m| (m.content, m.user_id))
-                    .collect();
-                
-                let count = self.bulk_insert_messages(messages).await?;
-                
-                Response::from_json(&serde_json::json!({
-                    "success": true,
-                    "inserted": count
-                }))
+                self.messages.iter().try_for_each(Validate::validate)
             }
-            
-            (Method::Get, "/stats") => {
-                let stats = self.get_statistics().await?;
-                Response::from_json(&stats)
-            }
-            
-            (Method::Get, "/export") => {
-                let dump = self.export_database().await?;
-                let mut response = Response::from_bytes(dump)?;
-                response.headers_mut().set("Content-Type", "text/plain; charset=utf-8")?;
+        }
+        
+        let body = match JsonBody::<BulkInsertRequest>::extract_limited(&mut req, BULK_INSERT_BODY_LIMIT).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let messages: Vec<(String, String)> = body.messages
+            .into_iter()
+            .map(|m| (m.content, m.user_id))
+            .collect();
+        
+        let count = ctx.data.bulk_insert_messages(messages, session(&req)?.as_deref()).await?;
+        
+        Response::from_json(&serde_json::json!({
+            "success": true,
+            "inserted": count
+        }))
+    }
+    
+    pub async fn stats(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let stats = ctx.data.get_statistics().await?;
+        Response::from_json(&stats)
+    }
+    
+    pub async fn export_database(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
+        
+        #[derive(Deserialize, Default)]
+        #[serde(rename_all = "lowercase")]
+        enum ImageFormat {
+            #[default]
+            Sql,
+            Binary,
+        }
+        
+        #[derive(Deserialize)]
+        struct ImageRequest {
+            #[serde(default)]
+            format: ImageFormat,
+        }
+        
+        impl Validate for ImageRequest {}
+        
+        let format = match Query::<ImageRequest>::extract(&url) {
+            Ok(Query(request)) => request.format,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let mut response = match format {
+            ImageFormat::Sql => {
+                let mut response = Response::ok(ctx.data.export_database().await?)?;
+                response.headers_mut().set("Content-Type", "application/sql; charset=utf-8")?;
                 response.headers_mut().set("Content-Disposition", "attachment; filename=\"database.sql\"")?;
-                Ok(response)
+                response
             }
-            
-            
-            (Method::Get, "/sql-test") => {
-                console_log!("Handling SQL test request");
-                self.sql_test().await
+            ImageFormat::Binary => {
+                let mut response = Response::from_bytes(ctx.data.export_database_image().await?)?;
+                response.headers_mut().set("Content-Type", "application/vnd.sqlite3")?;
+                response.headers_mut().set("Content-Disposition", "attachment; filename=\"database.sqlite3\"")?;
+                response
             }
-            
-            _ => {
-                console_log!("No match found for: method={:?}, api_path='{}', full_path='{}']", req.method(), api_path, path);
-                Response::error(format!("Not Found: {} {}", req.method(), api_path), 404)
+        };
+        response.headers_mut().set("Cache-Control", "no-store")?;
+        Ok(response)
+    }
+    
+    pub async fn import_database(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let script = req.text().await?;
+        if script.trim().is_empty() {
+            return Response::error("Import body must be a SQL script", 400);
+        }
+        
+        match ctx.data.import_database(&script).await {
+            Ok(rows_written) => Response::from_json(&serde_json::json!({
+                "success": true,
+                "rows_written": rows_written
+            })),
+            Err(e) => Response::from_json(&serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            })).map(|r| r.with_status(400)),
+        }
+    }
+    
+    pub async fn migrations(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let migrations = ctx.data.get_migration_status().await?;
+        Response::from_json(&serde_json::json!({
+            "current_version": migrations.iter().filter(|m| m.applied).map(|m| m.version).max().unwrap_or(0),
+            "migrations": migrations
+        }))
+    }
+    
+    pub async fn migrate(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        #[derive(Deserialize)]
+        struct MigrateRequest {
+            version: i32,
+        }
+        
+        impl Validate for MigrateRequest {
+            fn validate(&self) -> std::result::Result<(), Rejection> {
+                if self.version < 0 {
+                    return Err(Rejection::field("version", "version must not be negative"));
+                }
+                Ok(())
             }
         }
+        
+        let body = match JsonBody::<MigrateRequest>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        match ctx.data.migrate_to(body.version).await {
+            Ok(migrations) => Response::from_json(&serde_json::json!({
+                "success": true,
+                "current_version": body.version,
+                "migrations": migrations
+            })),
+            Err(e) => Response::from_json(&serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            })).map(|r| r.with_status(400)),
+        }
+    }
+    
+    pub async fn run_query(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        #[derive(Deserialize)]
+        struct QueryRequest {
+            sql: String,
+            max_rows: Option<usize>,
+            max_rows_read: Option<u64>,
+        }
+        
+        impl Validate for QueryRequest {
+            fn validate(&self) -> std::result::Result<(), Rejection> {
+                require_text("sql", &self.sql, MAX_QUERY_LENGTH)
+            }
+        }
+        
+        let body = match JsonBody::<QueryRequest>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let limits = QueryLimits {
+            max_rows: body.max_rows.unwrap_or(QUERY_DEFAULT_ROWS).clamp(1, QUERY_MAX_ROWS),
+            max_rows_read: body.max_rows_read.unwrap_or(QUERY_DEFAULT_ROWS_READ).clamp(1, QUERY_MAX_ROWS_READ),
+        };
+        
+        match ctx.data.run_query(&body.sql, limits).await {
+            Ok(result) => Response::from_json(&result),
+            Err(e) => Response::from_json(&serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            })).map(|r| r.with_status(400)),
+        }
+    }
+    
+    pub async fn set_profiling(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        #[derive(Deserialize)]
+        struct ProfileRequest {
+            enabled: bool,
+        }
+        
+        impl Validate for ProfileRequest {}
+        
+        let body = match JsonBody::<ProfileRequest>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        console_log!("Query profiling {}", if body.enabled { "enabled" } else { "disabled" });
+        ctx.data.statements.set_profiling(body.enabled);
+        Response::from_json(&ctx.data.profile())
+    }
+    
+    pub async fn reset_profile(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        ctx.data.statements.reset_profile();
+        Response::from_json(&ctx.data.profile())
+    }
+    
+    pub async fn schema(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let schema = ctx.data.get_schema().await?;
+        Response::from_json(&schema)
+    }
+    
+    pub async fn sql_test(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        console_log!("Handling SQL test request");
+        ctx.data.sql_test().await
+    }
+    
+    pub async fn edit_message(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let id = match message_id(&ctx) {
+            Ok(id) => id,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let author = match required_session(&req)? {
+            Ok(author) => author,
+            Err(response) => return Ok(response),
+        };
+        let body = match JsonBody::<EditRequest>::extract(&mut req).await {
+            Ok(JsonBody(body)) => body,
+            Err(rejection) => return rejection.into_response(),
+        };
+        console_log!("Editing message {}", id);
+        
+        let sql = ctx.data.sql()?;
+        let outcome = MessageStore::new(&sql).edit(id, &author, body.content, Date::now().as_millis() as i64)?;
+        ctx.data.changed_message(outcome, |message| MessageEvent::Edited { message })
+    }
+    
+    pub async fn delete_message(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let id = match message_id(&ctx) {
+            Ok(id) => id,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let author = match required_session(&req)? {
+            Ok(author) => author,
+            Err(response) => return Ok(response),
+        };
+        console_log!("Soft-deleting message {}", id);
+        
+        let sql = ctx.data.sql()?;
+        let outcome = MessageStore::new(&sql).soft_delete(id, &author, Date::now().as_millis() as i64)?;
+        ctx.data.changed_message(outcome, |message| MessageEvent::Deleted { message })
+    }
+    
+    pub async fn restore_message(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let id = match message_id(&ctx) {
+            Ok(id) => id,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let author = match required_session(&req)? {
+            Ok(author) => author,
+            Err(response) => return Ok(response),
+        };
+        console_log!("Restoring message {}", id);
+        
+        let sql = ctx.data.sql()?;
+        let outcome = MessageStore::new(&sql).restore(id, &author)?;
+        ctx.data.changed_message(outcome, |message| MessageEvent::Restored { message })
+    }
+    
+    pub async fn message_history(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let id = match message_id(&ctx) {
+            Ok(id) => id,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let sql = ctx.data.sql()?;
+        let store = MessageStore::new(&sql);
+        let Some(message) = store.get(id)?.filter(|m| m.deleted_at.is_none()) else {
+            return Refusal::Missing.into_response();
+        };
+        Response::from_json(&serde_json::json!({
+            "message": message,
+            "edits": store.history(id)?
+        }))
+    }
+    
+    pub async fn user_messages(req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let url = req.url()?;
+        
+        let mut request = match PageRequest::from_url(&url) {
+            Ok(request) => request,
+            Err(rejection) => return rejection.into_response(),
+        };
+        request.user_id = match PathParam::extract("user_id", ctx.param("id").map_or("", String::as_str)) {
+            Ok(user_id) => Some(user_id),
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let page = ctx.data.get_messages(&request).await?;
+        Response::from_json(&page)
+    }
+    
+    pub async fn profile(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        Response::from_json(&ctx.data.profile())
+    }
+    
+    pub async fn registered_rooms(_req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let rooms: Vec<RoomEntry> = Kv::new(ctx.data.state.storage()).list(&ROOMS).await?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect();
+        Response::from_json(&rooms)
+    }
+    
+    pub async fn record_room(mut req: Request, ctx: DoContext<'_, SqliteDO>) -> Result<Response> {
+        let entry = match JsonBody::<RoomEntry>::extract(&mut req).await {
+            Ok(JsonBody(entry)) => entry,
+            Err(rejection) => return rejection.into_response(),
+        };
+        
+        let mut kv = Kv::new(ctx.data.state.storage());
+        let key = ROOMS.key(&entry.name);
+        if kv.get(&key).await?.is_none() {
+            kv.put(&key, &entry).await?;
+        }
+        Response::from_json(&entry)
     }
 }
 
 pub async fn handler(req: Request, ctx: RouteContext<crate::utils::middleware::ValidationState>) -> Result<Response> {
-    let namespace = ctx.env.durable_object("SqliteDO")?;
-    let stub = namespace.id_from_name("sqlite-demo-instance")?.get_stub()?;
-    stub.fetch_with_request(req).await
-}
\ No newline at end of file
+    let room = ctx.param("room").map_or(DEFAULT_ROOM, String::as_str);
+    if !is_valid_room(room) {
+        return Response::error(format!("Invalid room name: {}", room), 400);
+    }
+    fetch_room(&ctx.env, room, &req, ctx.data.session.as_deref()).await
+}
+
+/// Rooms recorded in the registry
+pub async fn list_rooms(env: &Env) -> Result<Vec<RoomEntry>> {
+    let request = Request::new(&format!("https://sqlite/sqlite/{}/api/rooms", REGISTRY_ROOM), Method::Get)?;
+    let mut response = fetch_room(env, REGISTRY_ROOM, &request, None).await?;
+    response.json().await
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    fn store_database() -> NativeSqlite {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.migrate(MIGRATIONS).unwrap();
+        sql
+    }
+
+    fn newest(sql: &NativeSqlite, limit: u32) -> Vec<String> {
+        MessageStore::new(sql).page(&PageRequest { limit, ..Default::default() }).unwrap()
+            .messages
+            .into_iter()
+            .map(|m| m.content)
+            .collect()
+    }
+
+    #[test]
+    fn adds_and_lists_messages() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+
+        let first = store.add("hello".into(), "alice".into(), None, 1_000).unwrap();
+        store.add("it's me".into(), "bob".into(), None, 2_000).unwrap();
+        store.add("again".into(), "alice".into(), None, 3_000).unwrap();
+        assert_eq!(first.id, Some(1));
+
+        assert_eq!(newest(&sql, 2), ["again", "it's me"]);
+
+        let by_user = |user_id: &str| store.page(&PageRequest {
+            limit: 10,
+            user_id: Some(user_id.to_string()),
+            ..Default::default()
+        }).unwrap().messages;
+        assert_eq!(by_user("alice").iter().map(|m| m.timestamp).collect::<Vec<_>>(), [3_000, 1_000]);
+        assert!(by_user("' OR '1'='1").is_empty());
+    }
+
+    #[test]
+    fn computes_statistics() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+
+        let empty = store.statistics().unwrap();
+        assert_eq!((empty.total_messages, empty.first_message_time), (0, None));
+
+        store.insert_many(vec![("a".into(), "alice".into()), ("b".into(), "bob".into())], None, 5_000).unwrap();
+        store.add("c".into(), "alice".into(), None, 9_000).unwrap();
+
+        let stats = store.statistics().unwrap();
+        assert_eq!(stats.total_messages, 3);
+        assert_eq!(stats.unique_users, 2);
+        assert_eq!(stats.first_message_time, Some(5_000));
+        assert_eq!(stats.last_message_time, Some(9_000));
+    }
+
+    #[test]
+    fn deletes_all_messages() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        store.insert_many(vec![("a".into(), "alice".into()), ("b".into(), "bob".into())], None, 1).unwrap();
+
+        assert_eq!(store.delete_all().unwrap().changes, 2);
+        assert!(newest(&sql, 10).is_empty());
+    }
+
+    #[test]
+    fn failed_bulk_insert_inside_transaction_rolls_back_only_the_batch() {
+        let sql = store_database();
+        sql.transaction(|sql| {
+            let store = MessageStore::new(sql);
+            store.add("kept".into(), "alice".into(), None, 1)?;
+            sql.execute("CREATE TEMP TRIGGER reject AFTER INSERT ON messages WHEN NEW.content = 'bad' BEGIN SELECT RAISE(ABORT, 'rejected'); END")?;
+            let batch = store.insert_many(vec![("ok".into(), "bob".into()), ("bad".into(), "bob".into())], None, 2);
+            assert!(batch.is_err());
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(newest(&sql, 10), ["kept"]);
+    }
+    
+    #[test]
+    fn export_imports_into_another_instance() {
+        let source = store_database();
+        MessageStore::new(&source).insert_many(vec![("a".into(), "alice".into()), ("b".into(), "bob".into())], None, 7).unwrap();
+        
+        let target = store_database();
+        MessageStore::new(&target).add("stale".into(), "carol".into(), None, 1).unwrap();
+        target.import_sql(&source.dump_sql().unwrap()).unwrap();
+        
+        let contents = newest(&target, 10);
+        assert_eq!(contents.len(), 2);
+        assert!(!contents.contains(&"stale".to_string()));
+        assert!(target.migration_status(MIGRATIONS).unwrap().iter().all(|m| m.applied));
+    }
+    
+    #[test]
+    fn exports_filtered_messages_oldest_first() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        store.add("first".into(), "alice".into(), None, 100).unwrap();
+        store.add("second".into(), "bob".into(), None, 200).unwrap();
+        store.add("third".into(), "alice".into(), None, 300).unwrap();
+        
+        let contents = |filter: MessageFilter| -> Vec<String> {
+            store.export(&filter).unwrap()
+                .map(|row| row.unwrap().get::<String>("content").unwrap())
+                .collect()
+        };
+        
+        assert_eq!(contents(MessageFilter::default()), ["first", "second", "third"]);
+        assert_eq!(contents(MessageFilter { since: Some(200), ..Default::default() }), ["second", "third"]);
+        assert_eq!(contents(MessageFilter { until: Some(300), ..Default::default() }), ["first", "second"]);
+        assert_eq!(contents(MessageFilter { user_id: Some("alice".into()), since: Some(101), until: None }), ["third"]);
+        
+        let rows = store.export(&MessageFilter::default()).unwrap();
+        let columns = rows.columns().to_vec();
+        let csv = ExportChunks::new(ExportFormat::Csv, columns, rows).collect::<Result<Vec<_>>>().unwrap().concat();
+        assert!(String::from_utf8(csv).unwrap().starts_with("id,timestamp,content,user_id,edited_at\r\n1,100,first,alice,\r\n"));
+    }
+    
+    #[test]
+    fn pages_through_history_with_cursors() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        // Two messages share each timestamp, so the id breaks ties
+        for i in 0..7 {
+            store.add(format!("m{}", i), if i % 2 == 0 { "alice" } else { "bob" }.into(), None, 100 * (i / 2)).unwrap();
+        }
+        
+        let contents = |page: &MessagePage| page.messages.iter().map(|m| m.content.clone()).collect::<Vec<_>>();
+        
+        let first = store.page(&PageRequest { limit: 3, ..Default::default() }).unwrap();
+        assert_eq!(contents(&first), ["m6", "m5", "m4"]);
+        
+        let before = PageCursor::decode(first.next_cursor.as_deref().unwrap());
+        let second = store.page(&PageRequest { limit: 3, before, ..Default::default() }).unwrap();
+        assert_eq!(contents(&second), ["m3", "m2", "m1"]);
+        
+        let before = PageCursor::decode(second.next_cursor.as_deref().unwrap());
+        let last = store.page(&PageRequest { limit: 3, before, ..Default::default() }).unwrap();
+        assert_eq!(contents(&last), ["m0"]);
+        assert_eq!(last.next_cursor, None);
+        
+        // Walking forward from the oldest message returns newer ones, still newest first
+        let after = Some(PageCursor { timestamp: 0, id: 1 });
+        let newer = store.page(&PageRequest { limit: 2, after, ..Default::default() }).unwrap();
+        assert_eq!(contents(&newer), ["m2", "m1"]);
+        let after = PageCursor::decode(newer.next_cursor.as_deref().unwrap());
+        assert_eq!(after, Some(PageCursor { timestamp: 100, id: 3 }));
+        
+        let alice = store.page(&PageRequest { limit: 2, user_id: Some("alice".into()), ..Default::default() }).unwrap();
+        assert_eq!(contents(&alice), ["m6", "m4"]);
+        let before = PageCursor::decode(alice.next_cursor.as_deref().unwrap());
+        let alice = store.page(&PageRequest { limit: 2, before, user_id: Some("alice".into()), ..Default::default() }).unwrap();
+        assert_eq!(contents(&alice), ["m2", "m0"]);
+        assert_eq!(alice.next_cursor, None);
+    }
+    
+    #[test]
+    fn parses_page_requests() {
+        let parse = |query: &str| PageRequest::from_url(&Url::parse(&format!("https://example.com/messages?{}", query)).unwrap());
+        let cursor = PageCursor { timestamp: 1_700_000_000_000, id: 42 };
+        
+        let request = parse(&format!("limit=10&before={}", cursor.encode())).unwrap();
+        assert_eq!((request.limit, request.before, request.after), (10, Some(cursor), None));
+        assert_eq!(parse("").unwrap().limit, PageRequest::DEFAULT_LIMIT);
+        assert_eq!(parse("limit=100000").unwrap().limit, PageRequest::MAX_LIMIT);
+        assert_eq!(parse("limit=abc").unwrap_err().field.as_deref(), Some("limit"));
+        assert_eq!(parse("before=not-a-cursor").unwrap_err().field.as_deref(), Some("before"));
+        assert!(parse(&format!("before={0}&after={0}", cursor.encode())).is_err());
+    }
+    
+    #[test]
+    fn validates_search_requests_and_message_input() {
+        let parse = |query: &str| SearchRequest::from_url(&Url::parse(&format!("https://example.com/search?{}", query)).unwrap());
+        
+        let request = parse("q=hello+world&limit=1000&offset=5").unwrap();
+        assert_eq!((request.query.as_str(), request.limit, request.offset), ("hello world", SearchRequest::MAX_LIMIT, 5));
+        assert_eq!(parse("q=%20").unwrap_err().field.as_deref(), Some("q"));
+        assert_eq!(parse("q=x&offset=-1").unwrap_err().field.as_deref(), Some("offset"));
+        assert!(parse("limit=5").is_err());
+        
+        let input = |body: &str| JsonBody::<MessageInput>::parse(body.as_bytes(), 1024).map(|JsonBody(input)| input);
+        assert!(input(r#"{"content": "hi", "user_id": "alice"}"#).is_ok());
+        assert_eq!(input(r#"{"content": " ", "user_id": "alice"}"#).unwrap_err().field.as_deref(), Some("content"));
+        assert_eq!(input(&format!(r#"{{"content": "hi", "user_id": "{}"}}"#, "a".repeat(65))).unwrap_err().field.as_deref(), Some("user_id"));
+        assert_eq!(input(r#"{"content": "hi"}"#).unwrap_err().kind, "invalid_json");
+        
+        let post = |body: &str| JsonBody::<NewMessage>::parse(body.as_bytes(), 8192).map(|JsonBody(post)| post);
+        assert_eq!(post(r#"{"content": "hi", "user_id": "alice", "metadata": {"tags": ["a"]}}"#).unwrap().metadata, Some(serde_json::json!({"tags": ["a"]})));
+        assert!(post(r#"{"content": "hi", "user_id": "alice", "metadata": null}"#).is_ok());
+        assert_eq!(post(r#"{"content": "hi", "user_id": "alice", "metadata": [1]}"#).unwrap_err().field.as_deref(), Some("metadata"));
+        assert_eq!(post(&format!(r#"{{"content": "hi", "user_id": "alice", "metadata": {{"a": "{}"}}}}"#, "x".repeat(MAX_METADATA_BYTES))).unwrap_err().field.as_deref(), Some("metadata"));
+        assert_eq!(post(r#"{"content": "", "user_id": "alice"}"#).unwrap_err().field.as_deref(), Some("content"));
+    }
+    
+    #[test]
+    fn stores_metadata_and_filters_by_tag() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        let tagged = store.add_with_metadata("tagged".into(), "alice".into(), Some(serde_json::json!({"tags": ["news", "rust"]})), None, 1).unwrap();
+        store.add_with_metadata("other".into(), "bob".into(), Some(serde_json::json!({"tags": ["sports"]})), None, 2).unwrap();
+        store.add("plain".into(), "carol".into(), None, 3).unwrap();
+        
+        let stored = store.get(tagged.id.unwrap()).unwrap().unwrap();
+        assert_eq!(stored.metadata, Some(Json(serde_json::json!({"tags": ["news", "rust"]}))));
+        assert_eq!(store.get(3).unwrap().unwrap().metadata, None);
+        assert_eq!(serde_json::to_value(&stored).unwrap()["metadata"]["tags"][1], "rust");
+        
+        let by_tag = |tag: &str| store.page(&PageRequest { limit: 10, tag: Some(tag.into()), ..Default::default() }).unwrap()
+            .messages
+            .into_iter()
+            .map(|m| m.content)
+            .collect::<Vec<_>>();
+        assert_eq!(by_tag("rust"), ["tagged"]);
+        assert_eq!(by_tag("sports"), ["other"]);
+        assert!(by_tag("missing").is_empty());
+        
+        assert!(sql.execute("UPDATE messages SET metadata = 'not json' WHERE id = 3").is_err());
+    }
+    
+    fn search(sql: &NativeSqlite, query: &str, limit: u32, offset: u32) -> SearchResults {
+        MessageStore::new(sql).search(&SearchRequest { query: query.to_string(), limit, offset }).unwrap()
+    }
+    
+    #[test]
+    fn search_ranks_and_highlights_matches() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        store.add("the quick brown fox".into(), "alice".into(), None, 1).unwrap();
+        store.add("fox news about a fox & <friends>".into(), "bob".into(), None, 2).unwrap();
+        store.add("nothing to see here".into(), "carol".into(), None, 3).unwrap();
+        
+        let results = search(&sql, "fox", 10, 0);
+        assert_eq!(results.total, 2);
+        assert_eq!(results.hits[0].content, "fox news about a fox & <friends>");
+        assert_eq!(results.hits[0].snippet, "<mark>fox</mark> news about a <mark>fox</mark> &amp; &lt;friends&gt;");
+        assert!(results.hits[0].rank <= results.hits[1].rank);
+        
+        assert_eq!(search(&sql, "qui*", 10, 0).total, 1);
+        assert_eq!(search(&sql, "brown fox", 10, 0).total, 1);
+        // Operators and stray quotes are searched as text rather than parsed
+        assert_eq!(search(&sql, "fox\" OR NOT (", 10, 0).total, 0);
+        assert_eq!(search(&sql, "  ", 10, 0).total, 0);
+    }
+    
+    #[test]
+    fn search_pages_and_follows_edits() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        for i in 0..5 {
+            store.add(format!("hello number {}", i), "alice".into(), None, i).unwrap();
+        }
+        
+        let first = search(&sql, "hello", 2, 0);
+        assert_eq!((first.hits.len(), first.next_offset), (2, Some(2)));
+        let last = search(&sql, "hello", 2, 4);
+        assert_eq!((last.hits.len(), last.next_offset), (1, None));
+        
+        sql.execute("UPDATE messages SET content = 'goodbye' WHERE id = 1").unwrap();
+        sql.execute("DELETE FROM messages WHERE id = 2").unwrap();
+        assert_eq!(search(&sql, "hello", 10, 0).total, 3);
+        assert_eq!(search(&sql, "goodbye", 10, 0).total, 1);
+        
+        // Only updates to the indexed text rewrite the search index
+        let untouched = sql.prepare("UPDATE messages SET metadata = json_object('pinned', 1) WHERE id = 3").run().unwrap();
+        let reindexed = sql.prepare("UPDATE messages SET content = 'farewell' WHERE id = 3").run().unwrap();
+        assert!(untouched.rows_written < reindexed.rows_written, "{:?} vs {:?}", untouched, reindexed);
+        assert_eq!(search(&sql, "farewell", 10, 0).total, 1);
+    }
+    
+    #[test]
+    fn search_index_survives_export_and_import() {
+        let source = store_database();
+        MessageStore::new(&source).add("searchable text".into(), "alice".into(), None, 1).unwrap();
+        let dump = source.dump_sql().unwrap();
+        
+        let fresh = NativeSqlite::open_in_memory().unwrap();
+        fresh.import_sql(&dump).unwrap();
+        assert_eq!(search(&fresh, "searchable", 10, 0).total, 1);
+    }
+    
+    #[test]
+    fn edits_soft_deletes_and_restores_messages() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        let id = store.add("first draft".into(), "alice".into(), Some("alice-session"), 1).unwrap().id.unwrap();
+        let anonymous = store.add("other".into(), "bob".into(), None, 2).unwrap().id.unwrap();
+        
+        // Ownership follows the session, whatever user_id the message shows
+        assert_eq!(store.edit(id, "bob-session", "hijacked".into(), 3).unwrap().unwrap_err(), Refusal::OtherOwner);
+        assert_eq!(store.edit(id, "alice", "hijacked".into(), 3).unwrap().unwrap_err(), Refusal::OtherOwner);
+        assert_eq!(store.edit(anonymous, "bob", "hijacked".into(), 3).unwrap().unwrap_err(), Refusal::OtherOwner);
+        assert_eq!(store.edit(99, "alice-session", "missing".into(), 3).unwrap().unwrap_err(), Refusal::Missing);
+        
+        let edited = store.edit(id, "alice-session", "second draft".into(), 5).unwrap().unwrap();
+        assert_eq!((edited.content.as_str(), edited.edited_at), ("second draft", Some(5)));
+        store.edit(id, "alice-session", "final".into(), 7).unwrap().unwrap();
+        let history: Vec<String> = store.history(id).unwrap().into_iter().map(|e| e.previous_content).collect();
+        assert_eq!(history, ["first draft", "second draft"]);
+        assert_eq!(search(&sql, "final", 10, 0).total, 1);
+        assert_eq!(search(&sql, "draft", 10, 0).total, 0);
+        
+        assert_eq!(store.restore(id, "alice-session").unwrap().unwrap_err(), Refusal::NotYetDeleted);
+        assert_eq!(store.soft_delete(id, "bob-session", 8).unwrap().unwrap_err(), Refusal::OtherOwner);
+        let deleted = store.soft_delete(id, "alice-session", 8).unwrap().unwrap();
+        assert_eq!(deleted.deleted_at, Some(8));
+        assert_eq!(newest(&sql, 10), ["other"]);
+        assert_eq!(store.statistics().unwrap().total_messages, 1);
+        assert_eq!(search(&sql, "final", 10, 0).total, 0);
+        assert_eq!(store.export(&MessageFilter::default()).unwrap().count(), 1);
+        assert_eq!(store.soft_delete(id, "alice-session", 9).unwrap().unwrap_err(), Refusal::Missing);
+        assert_eq!(store.edit(id, "alice-session", "zombie".into(), 9).unwrap().unwrap_err(), Refusal::Missing);
+        
+        let restored = store.restore(id, "alice-session").unwrap().unwrap();
+        assert_eq!((restored.content.as_str(), restored.deleted_at), ("final", None));
+        assert_eq!(newest(&sql, 10), ["other", "final"]);
+    }
+    
+    #[test]
+    fn catches_up_on_changes_since_a_cursor() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        let ids: Vec<i64> = (0..5)
+            .map(|i| store.add(format!("m{}", i), "alice".into(), Some("alice"), 10 * i).unwrap().id.unwrap())
+            .collect();
+        let since = PageCursor { timestamp: 20, id: ids[2] };
+        
+        store.edit(ids[0], "alice", "m0 edited".into(), 30).unwrap().unwrap();
+        store.soft_delete(ids[1], "alice", 40).unwrap().unwrap();
+        store.soft_delete(ids[4], "alice", 40).unwrap().unwrap();
+        
+        let describe = |event: &MessageEvent| match event {
+            MessageEvent::Created { message, .. } => format!("created {}", message.content),
+            MessageEvent::Edited { message } => format!("edited {}", message.content),
+            MessageEvent::Deleted { message } => format!("deleted {}", message.content),
+            other => format!("{:?}", other),
+        };
+        
+        let (events, complete) = store.catch_up(since, 10).unwrap();
+        assert!(complete);
+        assert_eq!(events.iter().map(describe).collect::<Vec<_>>(), ["edited m0 edited", "deleted m1", "created m3"]);
+        
+        let (events, complete) = store.catch_up(PageCursor { timestamp: -1, id: 0 }, 2).unwrap();
+        assert!(!complete);
+        assert_eq!(events.iter().map(describe).collect::<Vec<_>>(), ["created m0 edited", "created m2"]);
+        
+        let json = serde_json::to_value(MessageEvent::created(store.get(ids[3]).unwrap().unwrap())).unwrap();
+        assert_eq!(json["type"], "created");
+        assert_eq!(PageCursor::decode(json["cursor"].as_str().unwrap()), Some(PageCursor { timestamp: 30, id: ids[3] }));
+    }
+    
+    #[test]
+    fn prunes_by_age_and_count_in_batches() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        for timestamp in 1..=10 {
+            store.add(format!("m{}", timestamp), "alice".into(), None, timestamp).unwrap();
+        }
+        let edited = store.add("edited".into(), "bob".into(), Some("bob"), 2).unwrap().id.unwrap();
+        store.edit(edited, "bob", "changed".into(), 3).unwrap().unwrap();
+        
+        assert_eq!(store.retention_policy().unwrap(), RetentionPolicy::default());
+        let policy = RetentionPolicy { max_age_ms: Some(5), max_rows: Some(4) };
+        store.set_retention_policy(policy, 10).unwrap();
+        assert_eq!(store.retention_policy().unwrap(), policy);
+        
+        // Five messages are older than the cutoff of 5; the batch takes three
+        let (first, ids) = store.prune(policy, 10, 3).unwrap();
+        assert_eq!(ids, [1, 2, edited]);
+        assert_eq!((first.deleted_by_age, first.deleted_by_count, first.pending), (3, 0, true));
+        
+        let (second, _) = store.prune(policy, 10, 3).unwrap();
+        assert_eq!((second.deleted_by_age, second.deleted_by_count, second.pending), (2, 1, true));
+        
+        let (third, _) = store.prune(policy, 10, 3).unwrap();
+        assert_eq!((third.deleted_by_age, third.deleted_by_count, third.pending), (0, 1, false));
+        
+        assert_eq!(newest(&sql, 10), ["m10", "m9", "m8", "m7"]);
+        assert_eq!(sql.execute("SELECT COUNT(*) FROM message_edits").unwrap().first::<(i64,)>().unwrap(), Some((0,)));
+        assert_eq!(store.retention_runs(10).unwrap(), [third, second, first]);
+    }
+    
+    #[test]
+    fn resolves_rooms_from_the_matched_pattern() {
+        // The room `api_handler` forwards to is the `:room` the Worker router matched
+        let mut worker = matchit::Router::new();
+        for pattern in SqliteDO::routes().merge(SqliteDO::admin_routes()).patterns() {
+            worker.insert(pattern, ()).unwrap();
+        }
+        let room = |path: &str| worker.at(path).unwrap().params.get("room").map(String::from);
+        assert_eq!(room("/sqlite/api/messages"), None);
+        assert_eq!(room("/sqlite/lobby/api/message/3/history").as_deref(), Some("lobby"));
+        assert_eq!(room("/sqlite/api-team/api/stats").as_deref(), Some("api-team"));
+        assert_eq!(room("/sqlite/api/api/messages").as_deref(), Some("api"));
+        assert_eq!(room("/sqlite/api/api/query").as_deref(), Some("api"));
+        
+        assert!(is_valid_room("team-42_a") && is_valid_room(DEFAULT_ROOM));
+        assert!(!is_valid_room(REGISTRY_ROOM) && !is_valid_room("") && !is_valid_room("a/b") && !is_valid_room(&"x".repeat(65)));
+    }
+    
+    #[test]
+    fn routes_are_served_for_every_room() {
+        let routes = SqliteDO::routes().merge(SqliteDO::admin_routes());
+        assert!(routes.patterns().iter().any(|p| p == "/sqlite/:room/api/message/:id/history"));
+        assert_eq!(routes.allowed_methods("/sqlite/api/messages"), [Method::Get, Method::Delete]);
+        assert_eq!(routes.allowed_methods("/sqlite/lobby/api/message/3"), [Method::Patch, Method::Delete]);
+        assert_eq!(routes.allowed_methods("/sqlite/lobby/api/retention"), [Method::Get, Method::Put]);
+        assert_eq!(routes.allowed_methods("/sqlite/api/restore"), [Method::Post]);
+        assert!(routes.allowed_methods("/sqlite/lobby/api/rooms").is_empty());
+        assert_eq!(SqliteDO::registry_routes().allowed_methods("/sqlite/_rooms/api/rooms"), [Method::Get, Method::Post]);
+    }
+    
+    #[test]
+    fn gates_admin_routes_whatever_room_they_name() {
+        // The Worker forwards every pattern and gates on the admin routes, as
+        // `sqlite::api_handler` does
+        let gated = SqliteDO::admin_routes();
+        let admin = |method: Method, path: &str| gated.handles(&method, path);
+        
+        for path in ["/sqlite/api/query", "/sqlite/api/api/query", "/sqlite/lobby/api/restore", "/sqlite/api/import", "/sqlite/api/migrations", "/sqlite/api/api/profile"] {
+            assert!(admin(Method::Post, path), "POST {} is not gated", path);
+        }
+        for path in ["/sqlite/api/export", "/sqlite/api/schema", "/sqlite/lobby/api/migrations"] {
+            assert!(admin(Method::Get, path) && admin(Method::Head, path), "GET {} is not gated", path);
+        }
+        assert!(admin(Method::Put, "/sqlite/lobby/api/retention") && !admin(Method::Get, "/sqlite/lobby/api/retention"));
+        assert!(!admin(Method::Get, "/sqlite/api/messages") && !admin(Method::Get, "/sqlite/api/api/messages") && !admin(Method::Get, "/sqlite/lobby/api/stats"));
+    }
+    
+    #[test]
+    fn logs_checkpoints_and_validates_restores() {
+        let sql = store_database();
+        let store = MessageStore::new(&sql);
+        for i in 0..CHECKPOINTS_KEPT + 2 {
+            store.record_checkpoint(&format!("bookmark-{}", i), "delete_messages", i).unwrap();
+        }
+        let checkpoints = store.checkpoints(2).unwrap();
+        assert_eq!(checkpoints[0], Checkpoint { bookmark: format!("bookmark-{}", CHECKPOINTS_KEPT + 1), reason: "delete_messages".into(), created_at: CHECKPOINTS_KEPT + 1 });
+        assert_eq!(store.checkpoints(1000).unwrap().len() as i64, CHECKPOINTS_KEPT);
+        
+        let restore = |body: &str| JsonBody::<RestoreRequest>::parse(body.as_bytes(), 1024).map(|JsonBody(restore)| restore);
+        assert!(restore(r#"{"timestamp": 5}"#).is_ok());
+        assert_eq!(restore(r#"{"bookmark": "0000"}"#).unwrap().bookmark.as_deref(), Some("0000"));
+        assert_eq!(restore("{}").unwrap_err().field.as_deref(), Some("timestamp"));
+        assert_eq!(restore(r#"{"timestamp": 5, "bookmark": "0000"}"#).unwrap_err().field.as_deref(), Some("bookmark"));
+        
+        let now = 40 * 24 * 60 * 60 * 1000;
+        assert!(check_recovery_time("at", now - 1000, now).is_ok());
+        assert!(check_recovery_time("at", now + 1, now).is_err());
+        assert_eq!(check_recovery_time("at", now - RECOVERY_WINDOW_MS - 1, now).unwrap_err().field.as_deref(), Some("at"));
+    }
+    
+    #[test]
+    fn migrations_revert_and_reapply() {
+        let sql = store_database();
+        MessageStore::new(&sql).add("kept".into(), "alice".into(), None, 1).unwrap();
+        
+        sql.migrate_to(MIGRATIONS, 1).unwrap();
+        let schema = sql.schema().unwrap();
+        assert!(schema.table("message_edits").is_none() && schema.table("messages_fts").is_none());
+        
+        sql.migrate(MIGRATIONS).unwrap();
+        assert_eq!(search(&sql, "kept", 10, 0).total, 1);
+        
+        sql.migrate_to(MIGRATIONS, 0).unwrap();
+        assert!(sql.schema().unwrap().table("messages").is_none());
+    }
+    
+    #[test]
+    fn gates_admin_methods_only() {
+        assert!(is_admin_route(&Method::Put, "/sqlite/api/retention"));
+        assert!(is_admin_route(&Method::Head, "/sqlite/lobby/api/export"));
+        assert!(!is_admin_route(&Method::Get, "/sqlite/api/retention"));
+        assert!(!is_admin_route(&Method::Post, "/sqlite/lobby/api/messages"));
+    }
+}
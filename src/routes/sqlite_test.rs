@@ -18,13 +18,15 @@ pub async fn handle(
     // Test 1: Access Storage from route context
     let test_1 = match ctx.env.durable_object("SqliteDO") {
         Ok(namespace) => {
-            match namespace.id_from_name("sqlite-demo-instance") {
+            match namespace.id_from_name(crate::routes::sqlite_do::DEFAULT_ROOM) {
                 Ok(id) => {
                     match id.get_stub() {
                         Ok(stub) => {
                             // Try to run SQL test via the DO
                             console_log!("Attempting to fetch from DO with path /sqlite/api/sql-test");
-                            match stub.fetch_with_str("/sqlite/api/sql-test").await {
+                            let mut request = Request::new("https://sqlite/sqlite/api/sql-test", Method::Get)?;
+                            request.headers_mut()?.set(crate::routes::sqlite_do::ROOM_HEADER, crate::routes::sqlite_do::DEFAULT_ROOM)?;
+                            match stub.fetch_with_request(request).await {
                                 Ok(mut response) => {
                                     match response.text().await {
                                         Ok(text) => TestResult {
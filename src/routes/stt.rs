@@ -5,6 +5,9 @@ use serde::{Deserialize, Serialize};
 use crate::BaseTemplate;
 use crate::utils::middleware::ValidationState;
 use crate::utils::templates::render_template;
+use crate::utils::do_router::{DoContext, DoRouter};
+use crate::utils::kv::{Key, Kv};
+use crate::utils::extract::{JsonBody, Rejection, Validate};
 use serde_json::json;
 use base64::Engine as _;
 use base64::engine::general_purpose::STANDARD;
@@ -22,12 +25,27 @@ pub async fn handler(_req: Request, ctx: RouteContext<ValidationState>) -> Resul
     }
 }
 
+/// When the session last changed, in milliseconds since the epoch
+const MODIFIED: Key<u64> = Key::fixed("modified");
+
+/// Largest control message accepted over the WebSocket
+const CONTROL_MESSAGE_LIMIT: usize = 1024;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct AudioChunk {
     chunk_type: String,  // "start" or "end"
     timestamp: u64,
 }
 
+impl Validate for AudioChunk {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        match self.chunk_type.as_str() {
+            "start" | "end" => Ok(()),
+            other => Err(Rejection::field("chunk_type", format!("Unknown chunk type: {}", other))),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct TranscriptionResult {
     text: String,
@@ -84,34 +102,24 @@ struct WhisperResponse {
     vtt: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AudioMessage {
-    buffer: Vec<u8>,
-    is_streaming: Option<bool>,
-}
-
 #[wasm_bindgen]
 pub struct SttDO {
     state: State,
     env: Env,
     modified: Option<u64>,
+    routes: DoRouter<SttDO>,
 }
 
-#[durable_object]
-impl DurableObject for SttDO {
-    fn new(state: State, env: Env) -> Self {
-        Self {
-            state,
-            env,
-            modified: None,
-        }
+impl SttDO {
+    /// Both paths open the transcription WebSocket
+    pub fn routes() -> DoRouter<SttDO> {
+        DoRouter::new()
+            .get("/stt/ws", Self::connect)
+            .get("/stt/audio", Self::connect)
     }
 
-    async fn fetch(&mut self, req: Request) -> Result<Response> {
-        console_log!("Fetch request received for STT DO");
-        self.update_modified().await?;
-
-        if !req.headers().get("Upgrade")?.map_or(false, |v| v.eq_ignore_ascii_case("websocket")) {
+    async fn connect(req: Request, ctx: DoContext<'_, SttDO>) -> Result<Response> {
+        if !req.headers().get("Upgrade")?.is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
             console_log!("Not a WebSocket upgrade request");
             return Response::error("Expected Upgrade: websocket", 426);
         }
@@ -120,7 +128,7 @@ impl DurableObject for SttDO {
         let server = pair.server;
         let client = pair.client;
 
-        self.state.accept_web_socket(&server);
+        ctx.data.state.accept_web_socket(&server);
         console_log!("New WebSocket connection accepted");
 
         // Send initial message as a proper TranscriptionResult
@@ -144,15 +152,35 @@ impl DurableObject for SttDO {
 
         Response::from_websocket(client)
     }
+}
+
+#[durable_object]
+impl DurableObject for SttDO {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            env,
+            modified: None,
+            routes: Self::routes(),
+        }
+    }
 
-    async fn websocket_message(&mut self, _ws: WebSocket, message: WebSocketIncomingMessage) -> Result<()> {
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
+        console_log!("Fetch request received for STT DO");
+        self.update_modified().await?;
+
+        let env = self.env.clone();
+        self.routes.run(&*self, req, env).await
+    }
+
+    async fn websocket_message(&mut self, ws: WebSocket, message: WebSocketIncomingMessage) -> Result<()> {
         self.update_modified().await?;
 
         match message {
             WebSocketIncomingMessage::String(msg) => {
                 console_log!("Received WebSocket message, length: {}", msg.len());
-                match serde_json::from_str::<AudioChunk>(&msg) {
-                    Ok(chunk) => {
+                match JsonBody::<AudioChunk>::parse(msg.as_bytes(), CONTROL_MESSAGE_LIMIT) {
+                    Ok(JsonBody(chunk)) => {
                         console_log!("Processing control signal: {}", chunk.chunk_type);
                         match chunk.chunk_type.as_str() {
                             "start" => {
@@ -161,16 +189,19 @@ impl DurableObject for SttDO {
                             "end" => {
                                 console_log!("End of audio stream");
                             },
-                            _ => console_log!("Unknown chunk type: {}", chunk.chunk_type),
+                            _ => {}
                         }
                     }
-                    Err(e) => console_log!("Failed to parse control message: {:?}", e),
+                    Err(rejection) => {
+                        console_log!("Rejected control message: {}", rejection.message);
+                        ws.send(&rejection)?;
+                    }
                 }
             }
             WebSocketIncomingMessage::Binary(data) => {
                 console_log!("Received binary message of size: {}", data.len());
                 
-                if data.len() > 0 {
+                if !data.is_empty() {
                     // Process raw audio data directly
                     match self.process_audio_chunk(data, true).await {
                         Ok(_) => console_log!("Successfully processed audio chunk"),
@@ -187,9 +218,9 @@ impl DurableObject for SttDO {
 
 impl SttDO {
     async fn update_modified(&mut self) -> Result<()> {
-        self.modified = Some(Date::now().as_millis());
-        self.state.storage().put("modified", self.modified).await?;
-        Ok(())
+        let modified = Date::now().as_millis();
+        self.modified = Some(modified);
+        Kv::new(self.state.storage()).put(&MODIFIED, &modified).await
     }
 
     // Confidence thresholds for transcription quality
@@ -549,16 +580,9 @@ pub mod do_handler {
     use super::*;
     
     pub async fn handler(req: Request, ctx: RouteContext<ValidationState>) -> Result<Response> {
-        let url = req.url()?;
-        let path = url.path();
-
-        // Both /stt/ws and /stt/audio should be handled by the DO
-        if path == "/stt/ws" || path == "/stt/audio" {
-            let namespace = ctx.env.durable_object("SttDO")?;
-            let stub = namespace.id_from_name("SttDO")?.get_stub()?;
-            stub.fetch_with_request(req).await
-        } else {
-            Response::error("Not found", 404)
-        }
+        // Only the paths in SttDO::routes are forwarded here
+        let namespace = ctx.env.durable_object("SttDO")?;
+        let stub = namespace.id_from_name("SttDO")?.get_stub()?;
+        stub.fetch_with_request(req).await
     }
 } 
\ No newline at end of file
@@ -4,6 +4,8 @@ use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 use crate::utils::scripture::get_scripture;
 use crate::utils::middleware::ValidationState;
+use crate::utils::do_router::{DoContext, DoRouter};
+use crate::utils::kv::{Kv, Namespace};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
@@ -19,6 +21,9 @@ struct ControlMessage {
     control_type: String,
 }
 
+/// Last value of each control, replayed to clients as they connect
+const CONTROLS: Namespace<ControlMessage> = Namespace::new("control");
+
 #[derive(Serialize, Deserialize, Debug)]
 struct TabBodyMessage {
     id: String,
@@ -29,6 +34,7 @@ struct TabBodyMessage {
 pub struct StudyDO {
     state: State,
     env: Env,
+    routes: DoRouter<StudyDO>,
 }
 
 impl StudyDO {
@@ -95,38 +101,47 @@ impl StudyDO {
     }
 }
 
-#[durable_object]
-impl DurableObject for StudyDO {
-    fn new(state: State, env: Env) -> Self {
-        Self { 
-            state,
-            env,
-        }        
+impl StudyDO {
+    pub fn routes() -> DoRouter<StudyDO> {
+        DoRouter::new().get("/study_do", Self::connect)
     }
 
-    async fn fetch(&mut self, req: Request) -> Result<Response> {
-        if !req.headers().get("Upgrade")?.map_or(false, |v| v.eq_ignore_ascii_case("websocket")) {
+    async fn connect(req: Request, ctx: DoContext<'_, StudyDO>) -> Result<Response> {
+        if !req.headers().get("Upgrade")?.is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
             return Response::error("Expected Upgrade: websocket", 426);
         }
 
         let pair = WebSocketPair::new()?;
         let server = pair.server;
         let client = pair.client;
-        self.state.accept_web_socket(&server);
+        ctx.data.state.accept_web_socket(&server);
 
-        let web_socket_conns = self.state.get_websockets();
+        let web_socket_conns = ctx.data.state.get_websockets();
         console_log!("study_web_socket_conns: {:?}", web_socket_conns.len());
-        self.broadcast_client_count(web_socket_conns.len()).await?;
+        ctx.data.broadcast_client_count(web_socket_conns.len()).await?;
 
-        let opts = ListOptions::new().prefix("control:");
-        let keys_map = self.state.storage().list_with_options(opts).await.unwrap();        
-        for key_str in keys_map.keys().into_iter().map(|k| k.unwrap().as_string().unwrap()) {
-            let _ = self.state.storage().get::<ControlMessage>(&key_str).await
-                .and_then(|control_message| server.send(&control_message));
-        }                
+        for (_, control_message) in Kv::new(ctx.data.state.storage()).list(&CONTROLS).await? {
+            let _ = server.send(&control_message);
+        }
 
         Response::from_websocket(client)
     }
+}
+
+#[durable_object]
+impl DurableObject for StudyDO {
+    fn new(state: State, env: Env) -> Self {
+        Self { 
+            state,
+            env,
+            routes: Self::routes(),
+        }        
+    }
+
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
+        let env = self.env.clone();
+        self.routes.run(&*self, req, env).await
+    }
 
     async fn websocket_message(&mut self, ws: WebSocket, message: WebSocketIncomingMessage) -> Result<()> {
         match message {
@@ -134,8 +149,9 @@ impl DurableObject for StudyDO {
                 match serde_json::from_str::<ControlMessage>(&msg) {
                     Ok(control_message) => {
                         console_log!("Received message: {:?}", control_message);
-                        let storage_key = format!("control:{}", control_message.control_name);
-                        self.state.storage().put(&storage_key, &control_message).await?;
+                        Kv::new(self.state.storage())
+                            .put(&CONTROLS.key(&control_message.control_name), &control_message)
+                            .await?;
                         
                         if let ControlValue::Text(text) = &control_message.control_value {
                             self.process_scripture_control(&control_message.control_name, text).await?;
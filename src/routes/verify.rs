@@ -44,7 +44,10 @@ pub async fn post_handler(mut req: Request, ctx: RouteContext<ValidationState>)
         let cookie_key = Key::derive_from(secret_bytes);
         let mut jar = CookieJar::new();
         
-        let cookie = Cookie::build("turnstile_validated", "true")
+        // A fresh id per validation, which also identifies the session to
+        // Durable Objects that need to know who made a change
+        let session = uuid::Uuid::new_v4().to_string();
+        let cookie = Cookie::build("turnstile_validated", session)
             .path("/")
             .max_age(cookie::time::Duration::days(7))
             .http_only(true)
@@ -4,6 +4,7 @@ use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use crate::utils::middleware::ValidationState;
+use crate::utils::kv::{Kv, Namespace};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
@@ -19,6 +20,9 @@ struct ControlMessage {
     control_type: String,
 }
 
+/// Last value of each control, replayed to clients as they connect
+const CONTROLS: Namespace<ControlMessage> = Namespace::new("control");
+
 #[wasm_bindgen]
 pub struct WebsocketDO {
     state: State,
@@ -62,7 +66,7 @@ impl WebsocketDO {
     }
 
     async fn schedule_next_alarm(&self) -> Result<()> {
-        if let Ok(control) = self.state.storage().get::<ControlMessage>("control:clock-enabled").await {
+        if let Some(control) = Kv::new(self.state.storage()).get(&CONTROLS.key("clock-enabled")).await? {
             if let ControlValue::Bool(false) = control.control_value {
                 return Ok(());
             }
@@ -85,10 +89,10 @@ impl DurableObject for WebsocketDO {
     async fn fetch(&mut self, req: Request) -> Result<Response> {
         if !self.is_new {
             self.is_new = true;
-            let _ = self.state.storage().delete_all().await?;
+            self.state.storage().delete_all().await?;
         }
 
-        if !req.headers().get("Upgrade")?.map_or(false, |v| v.eq_ignore_ascii_case("websocket")) {
+        if !req.headers().get("Upgrade")?.is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
             return Response::error("Expected Upgrade: websocket", 426);
         }
 
@@ -104,12 +108,9 @@ impl DurableObject for WebsocketDO {
         }
         self.broadcast_client_count(web_socket_conns.len()).await?;
 
-        let opts = ListOptions::new().prefix("control:");
-        let keys_map = self.state.storage().list_with_options(opts).await.unwrap();        
-        for key_str in keys_map.keys().into_iter().map(|k| k.unwrap().as_string().unwrap()) {
-            let _ = self.state.storage().get::<ControlMessage>(&key_str).await
-                .and_then(|control_message| server.send(&control_message));
-        }                
+        for (_, control_message) in Kv::new(self.state.storage()).list(&CONTROLS).await? {
+            let _ = server.send(&control_message);
+        }
 
         Response::from_websocket(client)
     }
@@ -120,9 +121,9 @@ impl DurableObject for WebsocketDO {
                 match serde_json::from_str::<ControlMessage>(&msg) {
                     Ok(control_message) => {
                         console_log!("Received message: {:?}", control_message);
-                        let storage_key = format!("control:{}", control_message.control_name);
-                        
-                        self.state.storage().put(&storage_key, &control_message).await?;
+                        Kv::new(self.state.storage())
+                            .put(&CONTROLS.key(&control_message.control_name), &control_message)
+                            .await?;
                         
                         if control_message.control_name == "clock-enabled" {
                             self.schedule_next_alarm().await?;
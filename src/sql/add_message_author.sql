@@ -0,0 +1,3 @@
+-- Session that posted each message, set by the Worker and never by clients.
+-- Older messages have none and cannot be changed.
+ALTER TABLE messages ADD COLUMN author TEXT;
@@ -0,0 +1 @@
+ALTER TABLE messages ADD COLUMN metadata TEXT CHECK (metadata IS NULL OR json_valid(metadata));
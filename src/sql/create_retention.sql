@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS retention_policy (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    max_age_ms INTEGER,
+    max_rows INTEGER,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS retention_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ran_at INTEGER NOT NULL,
+    deleted_by_age INTEGER NOT NULL,
+    deleted_by_count INTEGER NOT NULL,
+    pending INTEGER NOT NULL
+);
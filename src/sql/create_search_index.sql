@@ -0,0 +1,23 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+    content,
+    user_id UNINDEXED,
+    content='messages',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts(rowid, content, user_id) VALUES (new.id, new.content, new.user_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content, user_id) VALUES ('delete', old.id, old.content, old.user_id);
+END;
+
+CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content, user_id) VALUES ('delete', old.id, old.content, old.user_id);
+    INSERT INTO messages_fts(rowid, content, user_id) VALUES (new.id, new.content, new.user_id);
+END;
+
+-- Index messages stored before search existed
+INSERT INTO messages_fts(messages_fts) VALUES ('rebuild');
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_timestamp;
+DROP INDEX IF EXISTS idx_user_id;
\ No newline at end of file
@@ -1 +0,0 @@
-SELECT * FROM messages ORDER BY id
\ No newline at end of file
@@ -1 +0,0 @@
-SELECT * FROM messages ORDER BY timestamp DESC LIMIT {}
\ No newline at end of file
@@ -1,4 +1,5 @@
-SELECT sql FROM sqlite_master 
+SELECT type, name, tbl_name, sql FROM sqlite_master 
 WHERE type IN ('table', 'index') 
 AND name NOT LIKE 'sqlite_%'
-ORDER BY type, name
\ No newline at end of file
+AND name NOT LIKE '\_cf\_%' ESCAPE '\'
+ORDER BY type, name
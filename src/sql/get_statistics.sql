@@ -3,4 +3,5 @@ SELECT
     COUNT(DISTINCT user_id) as unique_users,
     MIN(timestamp) as first_message_time,
     MAX(timestamp) as last_message_time
-FROM messages
\ No newline at end of file
+FROM messages
+WHERE deleted_at IS NULL
\ No newline at end of file
@@ -1 +0,0 @@
-SELECT * FROM messages WHERE user_id = '{}' ORDER BY timestamp DESC
\ No newline at end of file
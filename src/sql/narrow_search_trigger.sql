@@ -0,0 +1,8 @@
+-- Only content and user_id are copied into the search index, so updates to
+-- other columns (soft-deletes, metadata, edit tracking) leave it alone
+DROP TRIGGER IF EXISTS messages_fts_update;
+
+CREATE TRIGGER messages_fts_update AFTER UPDATE OF content, user_id ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content, user_id) VALUES ('delete', old.id, old.content, old.user_id);
+    INSERT INTO messages_fts(rowid, content, user_id) VALUES (new.id, new.content, new.user_id);
+END;
@@ -0,0 +1,13 @@
+SELECT
+    m.id,
+    m.timestamp,
+    m.content,
+    m.user_id,
+    snippet(messages_fts, 0, char(57344), char(57345), '…', 16) AS snippet,
+    bm25(messages_fts) AS rank
+FROM messages_fts
+JOIN messages m ON m.id = messages_fts.rowid
+WHERE messages_fts MATCH :query
+AND m.deleted_at IS NULL
+ORDER BY rank, m.timestamp DESC
+LIMIT :limit OFFSET :offset
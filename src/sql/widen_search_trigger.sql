@@ -0,0 +1,6 @@
+DROP TRIGGER IF EXISTS messages_fts_update;
+
+CREATE TRIGGER messages_fts_update AFTER UPDATE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, content, user_id) VALUES ('delete', old.id, old.content, old.user_id);
+    INSERT INTO messages_fts(rowid, content, user_id) VALUES (new.id, new.content, new.user_id);
+END;
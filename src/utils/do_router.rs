@@ -0,0 +1,314 @@
+use worker::{Env, Method, Request, Response, Result, RouteContext, Router};
+use futures_util::future::LocalBoxFuture;
+use matchit::Router as PathRouter;
+use std::future::Future;
+use std::rc::Rc;
+
+type Handler<D> = Rc<dyn for<'r> Fn(Request, DoContext<'r, D>) -> LocalBoxFuture<'r, Result<Response>>>;
+type Params = Vec<(String, String)>;
+
+/// What a route handler gets besides the request: the Durable Object, its
+/// environment and the parameters of the matched pattern
+pub struct DoContext<'r, D> {
+    pub data: &'r D,
+    pub env: Env,
+    params: Params,
+}
+
+impl<D> DoContext<'_, D> {
+    /// The value of `:key` in the matched pattern
+    pub fn param(&self, key: &str) -> Option<&String> {
+        self.params.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+    }
+}
+
+/// An async function taking a request and a [`DoContext`], usable as a route
+/// handler for any lifetime of the object it is given
+pub trait DoHandler<'r, D: 'r> {
+    type Future: Future<Output = Result<Response>> + 'r;
+
+    fn call(&self, req: Request, ctx: DoContext<'r, D>) -> Self::Future;
+}
+
+impl<'r, D: 'r, F, T> DoHandler<'r, D> for F
+where
+    F: Fn(Request, DoContext<'r, D>) -> T,
+    T: Future<Output = Result<Response>> + 'r,
+{
+    type Future = T;
+
+    fn call(&self, req: Request, ctx: DoContext<'r, D>) -> T {
+        self(req, ctx)
+    }
+}
+
+/// Pins the closure to the higher-ranked signature of [`Handler`]
+fn handler<D, F>(func: F) -> F
+where
+    F: for<'r> Fn(Request, DoContext<'r, D>) -> LocalBoxFuture<'r, Result<Response>>,
+{
+    func
+}
+
+/// Routes of a Durable Object, declared once and used on both sides of the
+/// stub: the Worker forwards every pattern to the object with [`forward`],
+/// and the object dispatches requests to handlers with [`DoRouter::run`].
+///
+/// The path tables are built as routes are added, so an object keeps its
+/// router for its lifetime and only matches per request. A request whose
+/// path matches but whose method does not gets a 405 with an `Allow` header,
+/// HEAD is answered by the GET handler without a body, and OPTIONS lists the
+/// allowed methods.
+pub struct DoRouter<D> {
+    prefixes: Vec<&'static str>,
+    routes: Vec<(Method, String, Handler<D>)>,
+    /// One path table per method, as the worker `Router` keeps them
+    tables: Vec<(Method, PathRouter<Handler<D>>)>,
+}
+
+impl<D: 'static> Default for DoRouter<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: 'static> DoRouter<D> {
+    pub fn new() -> Self {
+        Self {
+            prefixes: vec![""],
+            routes: Vec::new(),
+            tables: Vec::new(),
+        }
+    }
+
+    /// Serve the routes added after this under each of `prefixes` rather
+    /// than at the root
+    pub fn prefixed(mut self, prefixes: &[&'static str]) -> Self {
+        self.prefixes = prefixes.to_vec();
+        self
+    }
+
+    pub fn route<F>(self, method: Method, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        let func: Handler<D> = Rc::new(handler(move |req, ctx| Box::pin(func.call(req, ctx))));
+        self.add(method, pattern, func)
+    }
+
+    pub fn get<F>(self, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        self.route(Method::Get, pattern, func)
+    }
+
+    pub fn post<F>(self, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        self.route(Method::Post, pattern, func)
+    }
+
+    pub fn put<F>(self, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        self.route(Method::Put, pattern, func)
+    }
+
+    pub fn patch<F>(self, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        self.route(Method::Patch, pattern, func)
+    }
+
+    pub fn delete<F>(self, pattern: &str, func: F) -> Self
+    where
+        F: for<'r> DoHandler<'r, D> + 'static,
+    {
+        self.route(Method::Delete, pattern, func)
+    }
+
+    /// Add the routes of `other` under this router's prefixes
+    pub fn merge(self, other: DoRouter<D>) -> Self {
+        other.routes.into_iter().fold(self, |router, (method, pattern, func)| router.add(method, &pattern, func))
+    }
+
+    fn add(mut self, method: Method, pattern: &str, func: Handler<D>) -> Self {
+        let index = match self.tables.iter().position(|(m, _)| *m == method) {
+            Some(index) => index,
+            None => {
+                self.tables.push((method.clone(), PathRouter::new()));
+                self.tables.len() - 1
+            }
+        };
+        for prefix in &self.prefixes {
+            let full = format!("{}{}", prefix, pattern);
+            if let Err(e) = self.tables[index].1.insert(full.as_str(), func.clone()) {
+                panic!("Invalid route {} {}: {}", method.as_ref(), full, e);
+            }
+        }
+        self.routes.push((method, pattern.to_string(), func));
+        self
+    }
+
+    /// Every distinct full pattern, in the order the routes were declared
+    pub fn patterns(&self) -> Vec<String> {
+        let mut patterns: Vec<String> = Vec::new();
+        for (_, pattern, _) in &self.routes {
+            for prefix in &self.prefixes {
+                let full = format!("{}{}", prefix, pattern);
+                if !patterns.contains(&full) {
+                    patterns.push(full);
+                }
+            }
+        }
+        patterns
+    }
+
+    /// Methods with a route matching `path`
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        Method::all()
+            .into_iter()
+            .filter(|method| self.find(method, path).is_some())
+            .collect()
+    }
+
+    /// Whether `run` would dispatch `method` on `path` to a handler, counting
+    /// HEAD as the GET it is answered by
+    pub fn handles(&self, method: &Method, path: &str) -> bool {
+        self.find(method, path).is_some() || (*method == Method::Head && self.find(&Method::Get, path).is_some())
+    }
+
+    /// The handler of `method` matching `path`, with the matched parameters
+    fn find(&self, method: &Method, path: &str) -> Option<(Handler<D>, Params)> {
+        let (_, table) = self.tables.iter().find(|(m, _)| m == method)?;
+        let matched = table.at(path).ok()?;
+        let params = matched.params.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        Some((matched.value.clone(), params))
+    }
+
+    pub async fn run(&self, data: &D, req: Request, env: Env) -> Result<Response> {
+        let path = req.path();
+        let method = req.method();
+
+        let head = method == Method::Head && self.find(&Method::Head, &path).is_none();
+        let lookup = if head { Method::Get } else { method.clone() };
+        if let Some((func, params)) = self.find(&lookup, &path) {
+            let response = func(req, DoContext { data, env, params }).await?;
+            if head {
+                return Ok(Response::empty()?
+                    .with_status(response.status_code())
+                    .with_headers(response.headers().clone()));
+            }
+            return Ok(response);
+        }
+
+        let allowed = self.allowed_methods(&path);
+        if allowed.is_empty() {
+            return Response::error("Not Found", 404);
+        }
+
+        let mut response = match method {
+            Method::Options => Response::empty()?.with_status(204),
+            _ => Response::error("Method Not Allowed", 405)?,
+        };
+        response.headers_mut().set("Allow", &allow_header(&allowed))?;
+        Ok(response)
+    }
+}
+
+/// `Allow` header value: the routed methods plus the HEAD and OPTIONS
+/// answered by `DoRouter::run`
+fn allow_header(allowed: &[Method]) -> String {
+    let mut methods: Vec<&str> = allowed.iter().map(Method::as_ref).collect();
+    if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+        methods.push("HEAD");
+    }
+    if !allowed.contains(&Method::Options) {
+        methods.push("OPTIONS");
+    }
+    methods.join(", ")
+}
+
+/// Register every pattern of a Durable Object's routes on the Worker's
+/// router, for all methods, so that `handler` can forward them to the object
+/// and it answers unsupported methods itself
+pub fn forward<'a, D: 'a, T>(
+    router: Router<'a, D>,
+    patterns: &[String],
+    handler: impl Fn(Request, RouteContext<D>) -> T + Copy + 'a,
+) -> Router<'a, D>
+where
+    T: Future<Output = Result<Response>> + 'a,
+{
+    patterns.iter().fold(router, |router, pattern| router.on_async(pattern, handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ok(_req: Request, _ctx: DoContext<'_, ()>) -> Result<Response> {
+        Response::ok("")
+    }
+
+    fn routes() -> DoRouter<()> {
+        DoRouter::new()
+            .prefixed(&["/api", "/rooms/:room/api"])
+            .get("/messages", ok)
+            .delete("/messages", ok)
+            .post("/message", ok)
+            .patch("/message/:id", ok)
+            .get("/message/:id/history", ok)
+    }
+
+    #[test]
+    fn expands_patterns_under_each_prefix() {
+        assert_eq!(routes().patterns(), [
+            "/api/messages",
+            "/rooms/:room/api/messages",
+            "/api/message",
+            "/rooms/:room/api/message",
+            "/api/message/:id",
+            "/rooms/:room/api/message/:id",
+            "/api/message/:id/history",
+            "/rooms/:room/api/message/:id/history",
+        ]);
+    }
+
+    #[test]
+    fn lists_allowed_methods_per_path() {
+        let routes = routes();
+        assert_eq!(routes.allowed_methods("/api/messages"), [Method::Get, Method::Delete]);
+        assert_eq!(routes.allowed_methods("/rooms/lobby/api/message/7"), [Method::Patch]);
+        assert_eq!(routes.allowed_methods("/rooms/lobby/api/message/7/history"), [Method::Get]);
+        assert!(routes.allowed_methods("/api/unknown").is_empty());
+        assert!(routes.allowed_methods("/messages").is_empty());
+
+        assert_eq!(allow_header(&[Method::Get, Method::Delete]), "GET, DELETE, HEAD, OPTIONS");
+        assert_eq!(allow_header(&[Method::Patch]), "PATCH, OPTIONS");
+    }
+
+    #[test]
+    fn handles_routed_methods_and_head_of_get() {
+        let routes = routes();
+        assert!(routes.handles(&Method::Delete, "/api/messages"));
+        assert!(routes.handles(&Method::Head, "/rooms/lobby/api/message/7/history"));
+        assert!(!routes.handles(&Method::Head, "/api/message/7"));
+        assert!(!routes.handles(&Method::Put, "/api/messages"));
+        assert!(!routes.handles(&Method::Options, "/api/messages"));
+    }
+
+    #[test]
+    fn merges_routes_under_the_receiving_prefixes() {
+        let merged = DoRouter::<()>::new().prefixed(&["/api"]).merge(DoRouter::new().post("/query", ok));
+        assert_eq!(merged.patterns(), ["/api/query"]);
+        assert_eq!(merged.allowed_methods("/api/query"), [Method::Post]);
+
+        let (_, params) = routes().find(&Method::Patch, "/rooms/lobby/api/message/7").unwrap();
+        assert_eq!(params, [("room".to_string(), "lobby".to_string()), ("id".to_string(), "7".to_string())]);
+    }
+}
@@ -0,0 +1,281 @@
+use worker::{Request, Response, Result, Url};
+use percent_encoding::percent_decode_str;
+use serde::de::value::{Error as ValueError, MapDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::str::FromStr;
+
+/// Largest JSON body accepted unless an endpoint sets its own limit
+pub const DEFAULT_BODY_LIMIT: usize = 64 * 1024;
+
+/// Why part of a request could not be extracted. Rendered as a JSON error
+/// with a 400 status, or 413 when a body is too large.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Rejection {
+    /// `invalid_query`, `invalid_path`, `invalid_json`, `invalid_field` or
+    /// `payload_too_large`
+    pub kind: &'static str,
+    /// The query parameter, path parameter or body field at fault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(rename = "error")]
+    pub message: String,
+}
+
+impl Rejection {
+    fn new(kind: &'static str, field: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            field: field.map(String::from),
+            message: message.into(),
+        }
+    }
+
+    /// A field that was read but failed validation
+    pub fn field(field: &str, message: impl Into<String>) -> Self {
+        Self::new("invalid_field", Some(field), message)
+    }
+
+    pub fn into_response(self) -> Result<Response> {
+        let status = if self.kind == "payload_too_large" { 413 } else { 400 };
+        Response::from_json(&serde_json::json!({
+            "success": false,
+            "error": self.message,
+            "kind": self.kind,
+            "field": self.field,
+        })).map(|r| r.with_status(status))
+    }
+}
+
+/// Checks run on an extracted value before it is handed to a handler
+pub trait Validate {
+    fn validate(&self) -> std::result::Result<(), Rejection> {
+        Ok(())
+    }
+}
+
+/// Fail unless `value` has between 1 and `max` characters after trimming
+pub fn require_text(field: &str, value: &str, max: usize) -> std::result::Result<(), Rejection> {
+    if value.trim().is_empty() {
+        Err(Rejection::field(field, format!("{} cannot be empty", field)))
+    } else if value.chars().count() > max {
+        Err(Rejection::field(field, format!("{} must be at most {} characters", field, max)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Typed, percent-decoded query string parameters
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned + Validate> Query<T> {
+    /// Deserialize the query string into `T`. Values are parsed into the
+    /// field types, so errors name the parameter at fault.
+    pub fn extract(url: &Url) -> std::result::Result<Self, Rejection> {
+        let current = RefCell::new(None::<String>);
+        let pairs = url.query_pairs().map(|(key, value)| {
+            *current.borrow_mut() = Some(key.to_string());
+            (key.into_owned(), QueryValue(value.into_owned()))
+        });
+
+        let value = T::deserialize(MapDeserializer::<_, ValueError>::new(pairs))
+            .map_err(|e| {
+                let message = e.to_string();
+                // Missing fields are reported by name; other errors are about the value just read
+                let field = if message.starts_with("missing field") { None } else { current.borrow().clone() };
+                match &field {
+                    Some(field) => Rejection::new("invalid_query", Some(field), format!("Invalid {}: {}", field, message)),
+                    None => Rejection::new("invalid_query", None, message),
+                }
+            })?;
+
+        value.validate()?;
+        Ok(Query(value))
+    }
+}
+
+/// A path segment, percent-decoded and parsed
+pub struct PathParam;
+
+impl PathParam {
+    pub fn extract<T: FromStr>(name: &str, raw: &str) -> std::result::Result<T, Rejection> {
+        let decoded = percent_decode_str(raw)
+            .decode_utf8()
+            .map_err(|_| Rejection::new("invalid_path", Some(name), format!("{} is not valid UTF-8", name)))?;
+        decoded.parse().map_err(|_| Rejection::new("invalid_path", Some(name), format!("Invalid {}: {}", name, decoded)))
+    }
+}
+
+/// A JSON request body, limited in size and validated
+#[derive(Debug)]
+pub struct JsonBody<T>(pub T);
+
+impl<T: DeserializeOwned + Validate> JsonBody<T> {
+    /// Read a body of up to `DEFAULT_BODY_LIMIT` bytes
+    pub async fn extract(req: &mut Request) -> std::result::Result<Self, Rejection> {
+        Self::extract_limited(req, DEFAULT_BODY_LIMIT).await
+    }
+
+    pub async fn extract_limited(req: &mut Request, limit: usize) -> std::result::Result<Self, Rejection> {
+        let declared = req.headers().get("Content-Length").ok().flatten().and_then(|l| l.parse::<usize>().ok());
+        if declared.is_some_and(|length| length > limit) {
+            return Err(too_large(limit));
+        }
+        let body = req.bytes().await
+            .map_err(|e| Rejection::new("invalid_json", None, format!("Failed to read body: {}", e)))?;
+        Self::parse(&body, limit)
+    }
+
+    /// Parse JSON text that arrived some other way, such as a WebSocket
+    /// message
+    pub fn parse(body: &[u8], limit: usize) -> std::result::Result<Self, Rejection> {
+        if body.len() > limit {
+            return Err(too_large(limit));
+        }
+        let value: T = serde_json::from_slice(body)
+            .map_err(|e| Rejection::new("invalid_json", None, format!("Invalid JSON body: {}", e)))?;
+        value.validate()?;
+        Ok(JsonBody(value))
+    }
+}
+
+fn too_large(limit: usize) -> Rejection {
+    Rejection::new("payload_too_large", None, format!("Body must be at most {} bytes", limit))
+}
+
+/// A query string value, parsed into whatever type the field asks for
+struct QueryValue(String);
+
+impl<'de> IntoDeserializer<'de, ValueError> for QueryValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, ValueError> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(e) => Err(de::Error::custom(e)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for QueryValue {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, ValueError> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, ValueError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, ValueError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Params {
+        q: String,
+        limit: Option<u32>,
+        order: Option<Order>,
+        exact: Option<bool>,
+    }
+
+    impl Validate for Params {
+        fn validate(&self) -> std::result::Result<(), Rejection> {
+            require_text("q", &self.q, 12)
+        }
+    }
+
+    fn query(query: &str) -> std::result::Result<Params, Rejection> {
+        Query::<Params>::extract(&Url::parse(&format!("https://example.com/?{}", query)).unwrap()).map(|Query(p)| p)
+    }
+
+    #[test]
+    fn extracts_decoded_typed_query_parameters() {
+        assert_eq!(query("q=caf%C3%A9+au+lait&limit=5&order=desc&exact=true&other=1").unwrap(), Params {
+            q: "café au lait".into(),
+            limit: Some(5),
+            order: Some(Order::Desc),
+            exact: Some(true),
+        });
+        assert_eq!(query("q=x").unwrap().limit, None);
+    }
+
+    #[test]
+    fn rejects_query_parameters_by_name() {
+        let invalid = query("q=x&limit=ten").unwrap_err();
+        assert_eq!((invalid.kind, invalid.field.as_deref()), ("invalid_query", Some("limit")));
+
+        assert_eq!(query("q=x&order=sideways").unwrap_err().field.as_deref(), Some("order"));
+        assert!(query("limit=1").unwrap_err().message.contains("missing field `q`"));
+        assert_eq!(query("q=++").unwrap_err(), Rejection::field("q", "q cannot be empty"));
+        assert_eq!(query("q=abcdefghijklm").unwrap_err().kind, "invalid_field");
+    }
+
+    #[test]
+    fn decodes_path_parameters() {
+        assert_eq!(PathParam::extract::<String>("user", "ann%20lee").unwrap(), "ann lee");
+        assert_eq!(PathParam::extract::<i64>("id", "42").unwrap(), 42);
+        assert_eq!(PathParam::extract::<i64>("id", "4x").unwrap_err().field.as_deref(), Some("id"));
+        assert!(PathParam::extract::<String>("user", "%FF").is_err());
+    }
+
+    #[test]
+    fn parses_limited_json_bodies() {
+        let JsonBody(params) = JsonBody::<Params>::parse(br#"{"q": "hi", "limit": 3}"#, 100).unwrap();
+        assert_eq!(params.limit, Some(3));
+
+        assert_eq!(JsonBody::<Params>::parse(br#"{"q": "hi"}"#, 4).unwrap_err().kind, "payload_too_large");
+        assert_eq!(JsonBody::<Params>::parse(b"{", 100).unwrap_err().kind, "invalid_json");
+        assert_eq!(JsonBody::<Params>::parse(br#"{"q": ""}"#, 100).unwrap_err().field.as_deref(), Some("q"));
+    }
+}
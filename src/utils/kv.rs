@@ -0,0 +1,230 @@
+use js_sys::{Map, Object, Reflect};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::time::Duration;
+use worker::wasm_bindgen::JsValue;
+use worker::{Date, Error, ListOptions, Storage};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Prefix under which expiry times are kept, as `"ttl:{key}"` holding the
+/// expiry in milliseconds since the epoch. No namespace may use it.
+const TTL_PREFIX: &str = "ttl:";
+
+/// A single storage key holding a `T`
+pub struct Key<T> {
+    key: Cow<'static, str>,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    /// A key outside any namespace, such as `"modified"`
+    pub const fn fixed(key: &'static str) -> Self {
+        Key { key: Cow::Borrowed(key), _value: PhantomData }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+
+    fn ttl_key(&self) -> String {
+        format!("{}{}", TTL_PREFIX, self.key)
+    }
+}
+
+/// A group of keys named `"{prefix}:{name}"`, each holding a `T`
+pub struct Namespace<T> {
+    prefix: &'static str,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Namespace<T> {
+    pub const fn new(prefix: &'static str) -> Self {
+        Namespace { prefix, _value: PhantomData }
+    }
+
+    /// The key of `name` in this namespace
+    pub fn key(&self, name: &str) -> Key<T> {
+        Key { key: Cow::Owned(format!("{}:{}", self.prefix, name)), _value: PhantomData }
+    }
+
+    /// The name of a full key in this namespace, or `None` for other keys
+    pub fn name_of<'k>(&self, key: &'k str) -> Option<&'k str> {
+        key.strip_prefix(self.prefix)?.strip_prefix(':')
+    }
+
+    fn scan_prefix(&self) -> String {
+        format!("{}:", self.prefix)
+    }
+}
+
+/// Whether an entry with the given expiry has lapsed at `now`
+fn is_expired(expires_at: Option<f64>, now: f64) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+fn read<T: DeserializeOwned>(key: &str, value: JsValue) -> Result<T> {
+    serde_wasm_bindgen::from_value(value).map_err(|e| Error::RustError(format!("Failed to read '{}' from storage: {}", key, e)))
+}
+
+fn key_string(key: JsValue) -> Result<String> {
+    key.as_string().ok_or_else(|| Error::RustError("Storage returned a key that is not a string".into()))
+}
+
+/// Typed access to a Durable Object's key-value storage. Values go through
+/// serde, missing keys read as `None`, and entries written with a TTL read as
+/// missing once it lapses until `purge_expired` removes them.
+pub struct Kv {
+    storage: Storage,
+}
+
+impl Kv {
+    pub fn new(storage: Storage) -> Self {
+        Kv { storage }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &Key<T>) -> Result<Option<T>> {
+        Ok(self.get_many(std::slice::from_ref(key)).await?.pop().flatten())
+    }
+
+    /// Values of several keys, in the order given, in one storage call
+    pub async fn get_many<T: DeserializeOwned>(&self, keys: &[Key<T>]) -> Result<Vec<Option<T>>> {
+        let mut lookup = Vec::with_capacity(keys.len() * 2);
+        for key in keys {
+            lookup.push(key.as_str().to_string());
+            lookup.push(key.ttl_key());
+        }
+        let found = self.storage.get_multiple(lookup).await?;
+
+        let now = Date::now().as_millis() as f64;
+        keys.iter()
+            .map(|key| {
+                let value = found.get(&JsValue::from_str(key.as_str()));
+                let expires_at = found.get(&JsValue::from_str(&key.ttl_key())).as_f64();
+                if value.is_undefined() || is_expired(expires_at, now) {
+                    Ok(None)
+                } else {
+                    read(key.as_str(), value).map(Some)
+                }
+            })
+            .collect()
+    }
+
+    /// Store `value`, clearing any TTL the key had
+    pub async fn put<T: Serialize>(&mut self, key: &Key<T>, value: &T) -> Result<()> {
+        self.storage.put(key.as_str(), value).await?;
+        self.storage.delete(&key.ttl_key()).await?;
+        Ok(())
+    }
+
+    /// Store `value` so that it reads as missing once `ttl` has passed
+    pub async fn put_with_ttl<T: Serialize>(&mut self, key: &Key<T>, value: &T, ttl: Duration) -> Result<()> {
+        let expires_at = Date::now().as_millis() as f64 + ttl.as_millis() as f64;
+        let entries = Object::new();
+        Reflect::set(&entries, &key.as_str().into(), &serde_wasm_bindgen::to_value(value)?)?;
+        Reflect::set(&entries, &key.ttl_key().into(), &JsValue::from_f64(expires_at))?;
+        self.storage.put_multiple_raw(entries).await
+    }
+
+    /// Store several values in one storage call, clearing their TTLs
+    pub async fn put_many<'a, T: Serialize + 'a>(&mut self, entries: impl IntoIterator<Item = (&'a Key<T>, &'a T)>) -> Result<()> {
+        let values = Object::new();
+        let mut ttl_keys = Vec::new();
+        for (key, value) in entries {
+            Reflect::set(&values, &key.as_str().into(), &serde_wasm_bindgen::to_value(value)?)?;
+            ttl_keys.push(key.ttl_key());
+        }
+        if ttl_keys.is_empty() {
+            return Ok(());
+        }
+        self.storage.put_multiple_raw(values).await?;
+        self.storage.delete_multiple(ttl_keys).await?;
+        Ok(())
+    }
+
+    /// Remove a key, returning whether it existed
+    pub async fn delete<T>(&mut self, key: &Key<T>) -> Result<bool> {
+        Ok(self.delete_many(std::slice::from_ref(key)).await? > 0)
+    }
+
+    /// Remove several keys, returning how many existed
+    pub async fn delete_many<T>(&mut self, keys: &[Key<T>]) -> Result<usize> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let ttl_keys: Vec<String> = keys.iter().map(Key::ttl_key).collect();
+        let deleted = self.storage.delete_multiple(keys.iter().map(Key::as_str).collect()).await?;
+        self.storage.delete_multiple(ttl_keys).await?;
+        Ok(deleted)
+    }
+
+    /// Every unexpired entry of a namespace as `(name, value)`, ordered by key
+    pub async fn list<T: DeserializeOwned>(&self, namespace: &Namespace<T>) -> Result<Vec<(String, T)>> {
+        let prefix = namespace.scan_prefix();
+        let entries = self.storage.list_with_options(ListOptions::new().prefix(&prefix)).await?;
+        let expiries = self.expiries(&prefix).await?;
+
+        let now = Date::now().as_millis() as f64;
+        let mut values = Vec::new();
+        for key in entries.keys() {
+            let key = key_string(key?)?;
+            let Some(name) = namespace.name_of(&key) else { continue };
+            let expires_at = expiries.get(&JsValue::from_str(&format!("{}{}", TTL_PREFIX, key))).as_f64();
+            if is_expired(expires_at, now) {
+                continue;
+            }
+            values.push((name.to_string(), read(&key, entries.get(&JsValue::from_str(&key)))?));
+        }
+        Ok(values)
+    }
+
+    /// Delete the entries of a namespace whose TTL has lapsed, returning how
+    /// many were removed
+    pub async fn purge_expired<T>(&mut self, namespace: &Namespace<T>) -> Result<usize> {
+        let expiries = self.expiries(&namespace.scan_prefix()).await?;
+
+        let now = Date::now().as_millis() as f64;
+        let mut expired = Vec::new();
+        for ttl_key in expiries.keys() {
+            let ttl_key = key_string(ttl_key?)?;
+            if is_expired(expiries.get(&JsValue::from_str(&ttl_key)).as_f64(), now) {
+                if let Some(name) = ttl_key.strip_prefix(TTL_PREFIX).and_then(|key| namespace.name_of(key)) {
+                    expired.push(namespace.key(name));
+                }
+            }
+        }
+        self.delete_many(&expired).await
+    }
+
+    /// Expiry times of the keys starting with `prefix`, keyed by TTL key
+    async fn expiries(&self, prefix: &str) -> Result<Map> {
+        let ttl_prefix = format!("{}{}", TTL_PREFIX, prefix);
+        self.storage.list_with_options(ListOptions::new().prefix(&ttl_prefix)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_keys_within_a_namespace() {
+        let controls: Namespace<String> = Namespace::new("control");
+        let key = controls.key("clock-enabled");
+        assert_eq!(key.as_str(), "control:clock-enabled");
+        assert_eq!(key.ttl_key(), "ttl:control:clock-enabled");
+        assert_eq!(controls.name_of(key.as_str()), Some("clock-enabled"));
+        assert_eq!(controls.name_of("controls:x"), None);
+        assert_eq!(controls.name_of("room:x"), None);
+        assert_eq!(Key::<u64>::fixed("modified").as_str(), "modified");
+    }
+
+    #[test]
+    fn expires_entries_with_a_lapsed_ttl() {
+        assert!(!is_expired(None, 1000.0));
+        assert!(!is_expired(Some(1001.0), 1000.0));
+        assert!(is_expired(Some(1000.0), 1000.0));
+    }
+}
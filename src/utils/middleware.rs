@@ -1,18 +1,13 @@
 use cookie::{Cookie, CookieJar, Key};
 use worker::*;
 
+#[derive(Default)]
 pub struct ValidationState {
     pub is_validated: bool,
     pub validation_message: String,
-}
-
-impl Default for ValidationState {
-    fn default() -> Self {
-        Self {
-            is_validated: false,
-            validation_message: String::new(),
-        }
-    }
+    /// Id of the validated session. Cookies issued before sessions had ids
+    /// validate without one.
+    pub session: Option<String>,
 }
 
 pub async fn validate_turnstile(req: Request, env: &worker::Env, _ctx: &Context) -> Result<(Request, ValidationState)> {
@@ -35,6 +30,7 @@ pub async fn validate_turnstile(req: Request, env: &worker::Env, _ctx: &Context)
             match jar.signed(&cookie_key).get("turnstile_validated") {
                 Some(cookie) => {
                     state.is_validated = true;
+                    state.session = uuid::Uuid::parse_str(cookie.value()).ok().map(|id| id.to_string());
                     cookie.value().to_string()
                 },
                 None => "Invalid Turnstile".to_string(),
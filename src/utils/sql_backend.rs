@@ -0,0 +1,730 @@
+use worker::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::utils::sql_dump;
+use crate::utils::sql_migrations::{self, Migration, MigrationStatus};
+use crate::utils::sql_row::{FromRow, IntoSqlValue, Row, SqlValue};
+use crate::utils::sql_schema::{self, Schema};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A SQLite database that statements can be executed against
+///
+/// Implemented by the Durable Object `DoSql` handle and, on native targets,
+/// by an embedded SQLite so the same code can run under `cargo test`.
+pub trait SqlBackend: Sized {
+    type Cursor: SqlCursor;
+
+    /// Execute a single statement with values bound to plain `?` placeholders
+    fn exec_with(&self, query: &str, bindings: &[SqlValue]) -> Result<Self::Cursor>;
+
+    /// Execute a SQL query and return a cursor over the results
+    fn execute(&self, query: &str) -> Result<Self::Cursor> {
+        self.exec_with(query, &[])
+    }
+
+    /// Rows changed by the most recent INSERT, UPDATE or DELETE, and the
+    /// rowid of the most recent INSERT, as `changes()` and
+    /// `last_insert_rowid()` report them
+    fn last_changes(&self) -> Result<(u64, i64)> {
+        self.execute("SELECT changes(), last_insert_rowid()")?
+            .first::<(u64, i64)>()?
+            .ok_or_else(|| Error::RustError("changes() returned no rows".into()))
+    }
+
+    /// Prepare a SQL statement for execution with bound parameters (D1-style API)
+    fn prepare(&self, query: &str) -> PreparedStatement<'_, Self> {
+        PreparedStatement::new(query.to_string(), self)
+    }
+
+    /// Parse the placeholders of a prepared statement. Backends may cache
+    /// the result by SQL text.
+    fn parse_statement(&self, query: &str) -> Result<Rc<ParsedStatement>> {
+        ParsedStatement::parse(query).map(Rc::new)
+    }
+
+    /// Execute multiple SQL statements in a transaction
+    ///
+    /// Transactions nest: an inner `transaction` that fails rolls back only
+    /// its own work, and the error propagates to the enclosing one.
+    fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Self) -> Result<R>;
+
+    /// Apply every pending migration
+    fn migrate(&self, migrations: &[Migration]) -> Result<()> {
+        let latest = migrations.last().map_or(0, |m| m.version);
+        self.migrate_to(migrations, latest)
+    }
+
+    /// Migrate the schema up or down to `target` (0 reverts everything)
+    fn migrate_to(&self, migrations: &[Migration], target: i32) -> Result<()> {
+        sql_migrations::migrate_to(self, migrations, target)
+    }
+
+    /// Report every known migration and whether it has been applied
+    fn migration_status(&self, migrations: &[Migration]) -> Result<Vec<MigrationStatus>> {
+        sql_migrations::migration_status(self, migrations)
+    }
+
+    /// Describe every table with its columns, indexes and foreign keys
+    fn schema(&self) -> Result<Schema> {
+        sql_schema::schema(self)
+    }
+
+    /// Dump the database as a SQL script that recreates its schema and rows
+    fn dump_sql(&self) -> Result<String> {
+        sql_dump::dump_sql(self)
+    }
+
+    /// Replay a SQL script, such as one produced by `dump_sql`, in a single
+    /// transaction. Returns the number of rows written.
+    fn import_sql(&self, script: &str) -> Result<u64> {
+        sql_dump::import_sql(self, script)
+    }
+}
+
+/// A backend that accepts `SAVEPOINT` statements through `exec`. The
+/// Durable Object `SqlStorage` rejects them, so `DoSql` nests transactions
+/// with `transactionSync` instead and does not implement this.
+pub trait Savepoints: SqlBackend {
+    /// Open a savepoint. Outside a transaction this starts one; inside, it
+    /// nests. The guard rolls back on drop unless released.
+    fn savepoint(&self) -> Result<Savepoint<'_, Self>> {
+        static NEXT_SAVEPOINT: AtomicU64 = AtomicU64::new(1);
+        let name = format!("sp_{}", NEXT_SAVEPOINT.fetch_add(1, Ordering::Relaxed));
+        self.execute(&format!("SAVEPOINT {}", name))?;
+        Ok(Savepoint {
+            sql: self,
+            name,
+            finished: false,
+        })
+    }
+}
+
+/// Run `f` inside a savepoint, releasing it on success and rolling it back
+/// on error
+pub fn savepoint_transaction<B, F, R>(sql: &B, f: F) -> Result<R>
+where
+    B: Savepoints,
+    F: FnOnce(&B) -> Result<R>,
+{
+    let savepoint = sql.savepoint()?;
+    // An error drops the guard, which rolls the savepoint back
+    let result = f(sql)?;
+    savepoint.release()?;
+    Ok(result)
+}
+
+/// Cursor over the result of a statement
+pub trait SqlCursor: Sized {
+    /// Column names of the result set
+    fn column_names(&self) -> Vec<String>;
+
+    /// Declared type of each column, or `None` for expressions and columns
+    /// declared without a type
+    fn column_types(&self) -> Vec<Option<String>>;
+
+    /// Read the next row, in `column_names()` order
+    fn next_row(&mut self) -> Result<Option<Vec<SqlValue>>>;
+
+    /// Number of rows SQLite has read so far, including rows scanned but not
+    /// returned. Grows as the cursor is iterated.
+    fn rows_read(&self) -> u64;
+
+    /// Number of rows written so far. Grows as the cursor is iterated.
+    fn rows_written(&self) -> u64;
+
+    /// Wall-clock time spent executing the statement, in milliseconds, or
+    /// `None` where it cannot be measured. A Durable Object's clock does not
+    /// advance while SQL runs, so its cursors report `None`.
+    fn duration_ms(&self) -> Option<f64>;
+
+    /// Stream rows one at a time, mapping each with `FromRow` as it is read
+    fn rows<T: FromRow>(self) -> Rows<Self, T> {
+        Rows {
+            columns: self.column_names().into(),
+            cursor: self,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get all rows as a vector of the specified type
+    fn collect<T: FromRow>(self) -> Result<Vec<T>> {
+        self.rows().collect()
+    }
+
+    /// Get the first row, if any
+    fn first<T: FromRow>(self) -> Result<Option<T>> {
+        self.rows().next().transpose()
+    }
+
+    /// Read every remaining row without deserializing it, so the statement
+    /// runs to completion and the row counters are final
+    fn drain(&mut self) -> Result<()> {
+        while self.next_row()?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Streaming iterator over the rows of a cursor
+///
+/// Rows are pulled from SQLite on demand, so only the current row is held in
+/// memory at a time.
+pub struct Rows<C, T> {
+    cursor: C,
+    columns: Rc<[String]>,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<C, T> Rows<C, T> {
+    /// The underlying cursor, for reading row counters while streaming
+    pub fn cursor(&self) -> &C {
+        &self.cursor
+    }
+
+    /// Column names of the result set
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+impl<C: SqlCursor, T: FromRow> Iterator for Rows<C, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.cursor.next_row() {
+            Ok(Some(values)) => Some(T::from_row(&Row::new(self.columns.clone(), values))),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Guard for an open savepoint, created by `Savepoints::savepoint`
+///
+/// Dropping the guard without calling `release` rolls back everything done
+/// since the savepoint was opened.
+pub struct Savepoint<'a, B: SqlBackend> {
+    sql: &'a B,
+    name: String,
+    finished: bool,
+}
+
+impl<B: SqlBackend> Savepoint<'_, B> {
+    /// Keep the changes. Releasing the outermost savepoint commits.
+    pub fn release(mut self) -> Result<()> {
+        self.finished = true;
+        self.sql.execute(&format!("RELEASE {}", self.name))?;
+        Ok(())
+    }
+
+    /// Discard the changes made since the savepoint was opened
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        self.undo()
+    }
+
+    fn undo(&self) -> Result<()> {
+        self.sql.execute(&format!("ROLLBACK TO {}", self.name))?;
+        self.sql.execute(&format!("RELEASE {}", self.name))?;
+        Ok(())
+    }
+}
+
+impl<B: SqlBackend> Drop for Savepoint<'_, B> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.undo();
+        }
+    }
+}
+
+/// Prepared SQL statement that mimics D1's prepare/bind API
+///
+/// Values are never spliced into the SQL text. Placeholders are rewritten to
+/// plain `?` and the bound values are passed to the backend in the order the
+/// placeholders appear, so SQLite binds them natively.
+///
+/// Supported placeholders:
+/// - `?` takes the next positional value (one past the highest index used so far)
+/// - `?NNN` takes the NNN-th positional value (1-based) and may be repeated
+/// - `:name`, `@name` and `$name` take the value bound with `bind_named`
+///
+/// Named and positional placeholders cannot be mixed in one statement.
+pub struct PreparedStatement<'a, B: SqlBackend> {
+    query: String,
+    sql: &'a B,
+    bindings: Vec<SqlValue>,
+    named: Vec<(String, SqlValue)>,
+    /// First value that could not be bound, reported when the statement runs
+    error: Option<String>,
+}
+
+impl<'a, B: SqlBackend> PreparedStatement<'a, B> {
+    fn new(query: String, sql: &'a B) -> Self {
+        Self {
+            query,
+            sql,
+            bindings: Vec::new(),
+            named: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A statement that fails with `error` when run, for builders whose
+    /// values could not be bound
+    pub(crate) fn failed(sql: &'a B, error: Error) -> Self {
+        Self { error: Some(error.to_string()), ..Self::new(String::new(), sql) }
+    }
+
+    /// Convert `value`, keeping the first failure, labelled with `target`,
+    /// to report when the statement runs
+    fn convert<T: IntoSqlValue>(&mut self, target: impl FnOnce() -> String, value: T) -> SqlValue {
+        value.into_sql_value().unwrap_or_else(|e| {
+            self.error.get_or_insert_with(|| format!("Cannot bind {}: {}", target(), e));
+            SqlValue::Null
+        })
+    }
+
+    /// Bind parameters to the prepared statement (D1-style)
+    /// Accepts an array of values to bind to ? placeholders
+    pub fn bind<I, T>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: IntoSqlValue,
+    {
+        self.bindings.clear();
+        for value in params {
+            let index = self.bindings.len() + 1;
+            let value = self.convert(|| format!("parameter ?{}", index), value);
+            self.bindings.push(value);
+        }
+        self
+    }
+
+    /// Bind a single value (convenience method)
+    pub fn bind_value<T: IntoSqlValue>(mut self, value: T) -> Self {
+        let index = self.bindings.len() + 1;
+        let value = self.convert(|| format!("parameter ?{}", index), value);
+        self.bindings.push(value);
+        self
+    }
+
+    /// Bind a value to a named placeholder. The name may be given with or
+    /// without its `:`, `@` or `$` prefix.
+    pub fn bind_named<T: IntoSqlValue>(mut self, name: &str, value: T) -> Self {
+        let name = name.trim_start_matches([':', '@', '$']).to_string();
+        let value = self.convert(|| format!("parameter :{}", name), value);
+        match self.named.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 = value,
+            None => self.named.push((name, value)),
+        }
+        self
+    }
+
+    /// Execute the statement and return all rows
+    pub fn all<T: FromRow>(&self) -> Result<Vec<T>> {
+        self.exec()?.collect()
+    }
+
+    /// Execute the statement and return the first row
+    pub fn first<T: FromRow>(&self) -> Result<Option<T>> {
+        self.exec()?.first()
+    }
+
+    /// Execute the statement and stream the rows one at a time
+    pub fn rows<T: FromRow>(&self) -> Result<Rows<B::Cursor, T>> {
+        Ok(self.exec()?.rows())
+    }
+
+    /// Execute the statement without returning rows (INSERT, UPDATE, DELETE)
+    /// Returns the affected row count, last insert id and execution counters
+    pub fn run(&self) -> Result<RunMeta> {
+        let mut cursor = self.exec()?;
+        cursor.drain()?;
+
+        // Read straight after the statement, which they then describe
+        let (changes, last_row_id) = self.sql.last_changes()?;
+
+        Ok(RunMeta {
+            changes,
+            last_row_id,
+            rows_read: cursor.rows_read(),
+            rows_written: cursor.rows_written(),
+            duration_ms: cursor.duration_ms(),
+        })
+    }
+
+    /// Execute the statement with its bindings passed through to SQLite
+    pub(crate) fn exec(&self) -> Result<B::Cursor> {
+        let (parsed, values) = self.resolve()?;
+        self.sql.exec_with(&parsed.query, &values)
+    }
+
+    /// Rewrite the query to plain `?` placeholders and line up the bound
+    /// values in placeholder order, checking arity along the way
+    fn resolve(&self) -> Result<(Rc<ParsedStatement>, Vec<SqlValue>)> {
+        if let Some(error) = &self.error {
+            return Err(Error::RustError(error.clone()));
+        }
+        let parsed = self.sql.parse_statement(&self.query)?;
+        let placeholders = &parsed.placeholders;
+
+        let has_named = placeholders.iter().any(|p| matches!(p, Placeholder::Named(_)));
+        let has_positional = placeholders.iter().any(|p| !matches!(p, Placeholder::Named(_)));
+        if has_named && has_positional {
+            return Err(Error::RustError(
+                "Cannot mix named and positional placeholders in one statement".into(),
+            ));
+        }
+        if has_named && !self.bindings.is_empty() {
+            return Err(Error::RustError(format!(
+                "Statement uses named placeholders but {} positional parameter(s) were bound",
+                self.bindings.len()
+            )));
+        }
+        if !has_named && !self.named.is_empty() {
+            return Err(Error::RustError(format!(
+                "Statement has no named placeholders but {} named parameter(s) were bound",
+                self.named.len()
+            )));
+        }
+
+        let mut values = Vec::with_capacity(placeholders.len());
+        let mut highest = 0;
+        let mut used_names = HashSet::new();
+
+        for placeholder in placeholders {
+            match placeholder {
+                Placeholder::Named(name) => {
+                    let value = self.named.iter()
+                        .find(|(n, _)| n == name)
+                        .map(|(_, v)| v.clone())
+                        .ok_or_else(|| Error::RustError(format!("No value bound for named parameter :{}", name)))?;
+                    values.push(value);
+                    used_names.insert(name.as_str());
+                }
+                positional => {
+                    let index = match positional {
+                        Placeholder::Indexed(index) => *index,
+                        _ => highest + 1,
+                    };
+                    highest = highest.max(index);
+                    let value = self.bindings.get(index - 1).ok_or_else(|| Error::RustError(format!(
+                        "Not enough parameters bound to query: ?{} referenced but {} bound",
+                        index,
+                        self.bindings.len()
+                    )))?;
+                    values.push(value.clone());
+                }
+            }
+        }
+
+        if highest < self.bindings.len() {
+            return Err(Error::RustError(format!(
+                "Too many parameters bound to query: {} expected but {} bound",
+                highest,
+                self.bindings.len()
+            )));
+        }
+        if let Some((unused, _)) = self.named.iter().find(|(n, _)| !used_names.contains(n.as_str())) {
+            return Err(Error::RustError(format!(
+                "Named parameter :{} is bound but not used by the statement",
+                unused
+            )));
+        }
+
+        Ok((parsed, values))
+    }
+}
+
+/// Result of `PreparedStatement::run`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RunMeta {
+    /// Rows inserted, updated or deleted by the statement
+    pub changes: u64,
+    /// Rowid of the most recent successful INSERT on this connection
+    pub last_row_id: i64,
+    pub rows_read: u64,
+    pub rows_written: u64,
+    /// `None` on Durable Objects, where statements cannot be timed
+    pub duration_ms: Option<f64>,
+}
+
+/// A statement with its placeholders rewritten to plain `?`, ready to have
+/// values lined up for them. Parsing depends only on the SQL text.
+#[derive(Debug)]
+pub struct ParsedStatement {
+    query: String,
+    placeholders: Vec<Placeholder>,
+}
+
+impl ParsedStatement {
+    pub fn parse(query: &str) -> Result<Self> {
+        let (query, placeholders) = scan_placeholders(query)?;
+        Ok(Self { query, placeholders })
+    }
+}
+
+/// A parameter placeholder found in a SQL statement
+#[derive(Debug, PartialEq)]
+enum Placeholder {
+    /// `?`
+    Next,
+    /// `?NNN` (1-based)
+    Indexed(usize),
+    /// `:name`, `@name` or `$name`, stored without the prefix
+    Named(String),
+}
+
+/// Scan a statement for placeholders, skipping string literals, quoted
+/// identifiers and comments. Returns the statement with every placeholder
+/// rewritten to a plain `?`, and the placeholders in order of appearance.
+fn scan_placeholders(query: &str) -> Result<(String, Vec<Placeholder>)> {
+    let bytes = query.as_bytes();
+    let mut rewritten = String::with_capacity(query.len());
+    let mut placeholders = Vec::new();
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            // 'string', "identifier" and `identifier`, with doubled quotes as escapes
+            quote @ (b'\'' | b'"' | b'`') => {
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == quote {
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 2;
+                            continue;
+                        }
+                        break;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            // [identifier]
+            b'[' => {
+                i = query[i..].find(']').map_or(bytes.len(), |end| i + end + 1);
+            }
+            // -- line comment
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = query[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+            }
+            // /* block comment */
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = query[i + 2..].find("*/").map_or(bytes.len(), |end| i + 2 + end + 2);
+            }
+            b'?' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let placeholder = if i == start + 1 {
+                    Placeholder::Next
+                } else {
+                    match query[start + 1..i].parse::<usize>() {
+                        Ok(index) if index > 0 => Placeholder::Indexed(index),
+                        _ => return Err(Error::RustError(format!(
+                            "Invalid placeholder {}: indexes start at ?1",
+                            &query[start..i]
+                        ))),
+                    }
+                };
+                rewritten.push_str(&query[copied..start]);
+                rewritten.push('?');
+                copied = i;
+                placeholders.push(placeholder);
+            }
+            b':' | b'@' | b'$' if bytes.get(i + 1).is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_') => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                rewritten.push_str(&query[copied..start]);
+                rewritten.push('?');
+                copied = i;
+                placeholders.push(Placeholder::Named(query[start + 1..i].to_string()));
+            }
+            _ => i += 1,
+        }
+    }
+
+    rewritten.push_str(&query[copied..]);
+    Ok((rewritten, placeholders))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    fn database() -> NativeSqlite {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, qty INTEGER)").unwrap();
+        sql
+    }
+
+    fn count(sql: &NativeSqlite) -> i64 {
+        sql.execute("SELECT COUNT(*) FROM items").unwrap().first::<(i64,)>().unwrap().unwrap().0
+    }
+
+    #[test]
+    fn scan_rewrites_placeholders_outside_literals() {
+        let (query, placeholders) = scan_placeholders(
+            "SELECT '?', \"a:b\", [x?] FROM t -- ?\nWHERE a = ?2 /* :c */ AND b = :name AND c = ?",
+        ).unwrap();
+        assert_eq!(query, "SELECT '?', \"a:b\", [x?] FROM t -- ?\nWHERE a = ? /* :c */ AND b = ? AND c = ?");
+        assert_eq!(placeholders, vec![
+            Placeholder::Indexed(2),
+            Placeholder::Named("name".into()),
+            Placeholder::Next,
+        ]);
+        assert!(scan_placeholders("SELECT ?0").is_err());
+    }
+
+    #[test]
+    fn binds_positional_indexed_and_named_values() {
+        let sql = database();
+        sql.prepare("INSERT INTO items (name, qty) VALUES (?, ?)").bind_value("it's").bind_value(3).run().unwrap();
+        sql.prepare("INSERT INTO items (name, qty) VALUES (?1, length(?1))").bind_value("four").run().unwrap();
+        sql.prepare("INSERT INTO items (name, qty) VALUES (:name, @qty)")
+            .bind_named("qty", 7)
+            .bind_named(":name", "named")
+            .run()
+            .unwrap();
+
+        let rows = sql.prepare("SELECT name, qty FROM items ORDER BY id").all::<(String, i64)>().unwrap();
+        assert_eq!(rows, vec![("it's".into(), 3), ("four".into(), 4), ("named".into(), 7)]);
+    }
+
+    #[test]
+    fn round_trips_blobs_and_64_bit_integers() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute("CREATE TABLE snippets (id INTEGER, ref INTEGER, audio BLOB)").unwrap();
+
+        let snowflake: i64 = 1_234_567_890_123_456_789;
+        let audio = vec![0u8, 1, 254, 255];
+        sql.prepare("INSERT INTO snippets VALUES (?, ?, ?), (?, ?, ?)")
+            .bind_value(snowflake).bind_value(i64::MAX as u64).bind_value(audio.clone())
+            .bind_value(i64::MIN).bind_value(42u64).bind_value(&b"\x00raw"[..])
+            .run()
+            .unwrap();
+
+        let rows = sql.prepare("SELECT id, ref, audio FROM snippets ORDER BY rowid").all::<(i64, u64, Vec<u8>)>().unwrap();
+        assert_eq!(rows, vec![(snowflake, i64::MAX as u64, audio), (i64::MIN, 42, b"\x00raw".to_vec())]);
+        assert_eq!(sql.prepare("SELECT typeof(audio) FROM snippets").all::<(String,)>().unwrap(), vec![("blob".into(),); 2]);
+        assert_eq!(sql.prepare("SELECT ref FROM snippets ORDER BY ref").all::<(u64,)>().unwrap(), vec![(42,), (i64::MAX as u64,)]);
+
+        // Neither direction reinterprets the sign bit
+        let error = sql.prepare("INSERT INTO snippets (ref) VALUES (?)").bind_value(u64::MAX).run().unwrap_err();
+        assert!(error.to_string().contains("?1") && error.to_string().contains("out of range"), "{}", error);
+        sql.execute("INSERT INTO snippets (ref) VALUES (-1)").unwrap();
+        let error = sql.prepare("SELECT ref FROM snippets WHERE ref < 0").all::<(u64,)>().unwrap_err();
+        assert!(error.to_string().contains("`ref`") && error.to_string().contains("-1 is out of range"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_arity_mismatches() {
+        let sql = database();
+        let insert = "INSERT INTO items (name, qty) VALUES (?, ?)";
+        assert!(sql.prepare(insert).bind_value("a").run().is_err());
+        assert!(sql.prepare(insert).bind_value("a").bind_value(1).bind_value(2).run().is_err());
+        assert!(sql.prepare("SELECT ? , :name").bind_value(1).bind_named("name", 2).run().is_err());
+        assert!(sql.prepare("SELECT :a").bind_named("a", 1).bind_named("b", 2).run().is_err());
+        assert!(sql.prepare("SELECT ?").bind_named("a", 1).run().is_err());
+        assert_eq!(count(&sql), 0);
+    }
+
+    #[test]
+    fn run_reports_changes_and_last_row_id() {
+        let sql = database();
+        let meta = sql.prepare("INSERT INTO items (name, qty) VALUES (?, ?)").bind_value("a").bind_value(1).run().unwrap();
+        assert_eq!((meta.changes, meta.last_row_id, meta.rows_written), (1, 1, 1));
+
+        sql.prepare("INSERT INTO items (name, qty) VALUES ('b', 2), ('c', 3)").run().unwrap();
+        let meta = sql.prepare("UPDATE items SET qty = qty + 1 WHERE qty > ?").bind_value(1).run().unwrap();
+        assert_eq!(meta.changes, 2);
+    }
+
+    #[test]
+    fn iterates_rows_and_counts_reads() {
+        let sql = database();
+        sql.execute("INSERT INTO items (name, qty) VALUES ('a', 1), ('b', 2), ('c', 3)").unwrap();
+
+        // The native backend has already read every row, so this checks the
+        // iterator's mapping rather than lazy stepping
+        let mut rows = sql.prepare("SELECT * FROM items ORDER BY id").rows::<Row>().unwrap();
+        let first = rows.next().unwrap().unwrap();
+        assert_eq!(first.get::<String>("name").unwrap(), "a");
+        assert_eq!(rows.count(), 2);
+
+        // Rows scanned count even when few or none are returned
+        let scan = sql.prepare("SELECT max(a.qty + b.qty) FROM items a CROSS JOIN items b").rows::<Row>().unwrap();
+        assert!(scan.cursor().rows_read() >= 6, "read {}", scan.cursor().rows_read());
+        let meta = sql.prepare("DELETE FROM items WHERE name = 'none'").run().unwrap();
+        assert!(meta.rows_read >= 2 && meta.changes == 0, "read {}", meta.rows_read);
+    }
+
+    #[test]
+    fn failed_transaction_rolls_back() {
+        let sql = database();
+        let result: Result<()> = sql.transaction(|sql| {
+            sql.execute("INSERT INTO items (name, qty) VALUES ('a', 1)")?;
+            Err(Error::RustError("boom".into()))
+        });
+        assert!(result.is_err());
+        assert_eq!(count(&sql), 0);
+    }
+
+    #[test]
+    fn nested_transaction_rolls_back_only_its_own_work() {
+        let sql = database();
+        sql.transaction(|sql| {
+            sql.execute("INSERT INTO items (name, qty) VALUES ('outer', 1)")?;
+            let inner: Result<()> = sql.transaction(|sql| {
+                sql.execute("INSERT INTO items (name, qty) VALUES ('inner', 2)")?;
+                Err(Error::RustError("inner failed".into()))
+            });
+            assert!(inner.is_err());
+            Ok(())
+        }).unwrap();
+
+        let names = sql.execute("SELECT name FROM items").unwrap().collect::<(String,)>().unwrap();
+        assert_eq!(names, vec![("outer".to_string(),)]);
+    }
+
+    #[test]
+    fn dropped_savepoint_rolls_back() {
+        let sql = database();
+        {
+            let _savepoint = sql.savepoint().unwrap();
+            sql.execute("INSERT INTO items (name, qty) VALUES ('a', 1)").unwrap();
+        }
+        assert_eq!(count(&sql), 0);
+
+        let savepoint = sql.savepoint().unwrap();
+        sql.execute("INSERT INTO items (name, qty) VALUES ('a', 1)").unwrap();
+        savepoint.release().unwrap();
+        assert_eq!(count(&sql), 1);
+    }
+}
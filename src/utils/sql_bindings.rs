@@ -1,8 +1,12 @@
 use worker::{Error, wasm_bindgen, js_sys};
-use serde::de::DeserializeOwned;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use js_sys::Array;
+use wasm_bindgen::closure::ScopedClosure;
+use js_sys::{Array, ArrayBuffer, IteratorNext, Promise, Uint8Array};
+use wasm_bindgen_futures::JsFuture;
+use std::time::Duration;
+use crate::utils::sql_backend::{PreparedStatement, SqlBackend, SqlCursor};
+use crate::utils::sql_row::{IntoSqlValue, SqlValue};
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -16,6 +20,10 @@ extern "C" {
     #[wasm_bindgen(method, catch)]
     pub fn exec(this: &SqlStorage, query: &str) -> std::result::Result<SqlStorageCursor, JsValue>;
 
+    // exec(query, ...bindings) - values are bound natively to ? placeholders
+    #[wasm_bindgen(method, catch, variadic, js_name = exec)]
+    pub fn exec_with_bindings(this: &SqlStorage, query: &str, bindings: Box<[JsValue]>) -> std::result::Result<SqlStorageCursor, JsValue>;
+
     #[wasm_bindgen(method, catch)]
     pub fn dump(this: &SqlStorage) -> std::result::Result<Vec<u8>, JsValue>;
 }
@@ -40,50 +48,161 @@ extern "C" {
 
     #[wasm_bindgen(method)]
     pub fn all(this: &SqlStorageCursor) -> JsValue;
+
+    #[wasm_bindgen(method, getter, js_name = rowsRead)]
+    pub fn rows_read(this: &SqlStorageCursor) -> f64;
+
+    #[wasm_bindgen(method, getter, js_name = rowsWritten)]
+    pub fn rows_written(this: &SqlStorageCursor) -> f64;
+
+    // Iterator protocol - yields one row object per call
+    #[wasm_bindgen(method, catch)]
+    pub fn next(this: &SqlStorageCursor) -> std::result::Result<IteratorNext, JsValue>;
+
+    // Iterator over rows as arrays of column values
+    #[wasm_bindgen(method)]
+    pub fn raw(this: &SqlStorageCursor) -> js_sys::Iterator;
 }
 
 pub struct Cursor {
     inner: SqlStorageCursor,
+    iter: js_sys::Iterator,
 }
 
 impl Cursor {
     fn new(inner: SqlStorageCursor) -> Self {
-        Self { inner }
+        let iter = inner.raw();
+        Self { inner, iter }
     }
     
-    /// Get all rows as a vector of the specified type
-    pub fn collect<T: DeserializeOwned>(self) -> Result<Vec<T>> {
-        let array = self.inner.toArray();
-        let len = array.length();
-        let mut results = Vec::with_capacity(len as usize);
-        
-        for i in 0..len {
-            let value = array.get(i);
-            let deserialized: T = serde_wasm_bindgen::from_value(value)
-                .map_err(|e| Error::JsError(format!("Failed to deserialize value: {:?}", e)))?;
-            results.push(deserialized);
+    /// Stream rows as arrays of column values, in `column_names()` order
+    pub fn raw(self) -> RawRows {
+        RawRows {
+            cursor: self,
+            done: false,
         }
-        
-        Ok(results)
     }
     
-    /// Get the first row, if any
-    pub fn first<T: DeserializeOwned>(self) -> Result<Option<T>> {
-        let array = self.inner.toArray();
-        if array.length() == 0 {
-            return Ok(None);
+    /// Read the next row as an array of column values
+    fn next_raw(&mut self) -> Result<Option<Vec<JsValue>>> {
+        let next = self.iter.next()
+            .map_err(|e| Error::JsError(format!("Failed to read row: {:?}", e)))?;
+        if next.done() {
+            Ok(None)
+        } else {
+            Ok(Some(next.value().unchecked_into::<Array>().to_vec()))
+        }
+    }
+}
+
+impl SqlCursor for Cursor {
+    fn column_names(&self) -> Vec<String> {
+        self.inner.column_names()
+            .iter()
+            .filter_map(|name| name.as_string())
+            .collect()
+    }
+    
+    fn column_types(&self) -> Vec<Option<String>> {
+        self.inner.column_types()
+            .iter()
+            .map(|kind| kind.as_string().filter(|k| !k.is_empty()))
+            .collect()
+    }
+    
+    fn next_row(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        Ok(self.next_raw()?.map(|values| values.iter().map(sql_value_from_js).collect()))
+    }
+    
+    fn rows_read(&self) -> u64 {
+        self.inner.rows_read() as u64
+    }
+    
+    fn rows_written(&self) -> u64 {
+        self.inner.rows_written() as u64
+    }
+    
+    /// `Date.now()` does not advance during synchronous execution, so the
+    /// time spent in SQLite cannot be measured here
+    fn duration_ms(&self) -> Option<f64> {
+        None
+    }
+}
+
+/// Convert a column value produced by the runtime into a `SqlValue`
+fn sql_value_from_js(value: &JsValue) -> SqlValue {
+    if value.is_null() || value.is_undefined() {
+        SqlValue::Null
+    } else if let Some(n) = value.as_f64() {
+        SqlValue::from_f64(n)
+    } else if let Ok(i) = i64::try_from(value.clone()) {
+        // A BigInt, as SQLite integers beyond the safe range arrive
+        SqlValue::Integer(i)
+    } else if let Some(s) = value.as_string() {
+        SqlValue::Text(s)
+    } else if let Some(b) = value.as_bool() {
+        SqlValue::Integer(b as i64)
+    } else if value.is_instance_of::<ArrayBuffer>() || value.is_instance_of::<Uint8Array>() {
+        SqlValue::Blob(Uint8Array::new(value).to_vec())
+    } else {
+        SqlValue::Text(js_sys::JSON::stringify(value).ok().and_then(|s| s.as_string()).unwrap_or_default())
+    }
+}
+
+/// Convert a bound value into the JS value passed to `exec`. Integers a JS
+/// number cannot hold exactly are passed as a `BigInt`.
+fn sql_value_to_js(value: &SqlValue) -> JsValue {
+    match value {
+        SqlValue::Null => JsValue::NULL,
+        SqlValue::Integer(i) if i.unsigned_abs() <= MAX_SAFE_INTEGER => JsValue::from_f64(*i as f64),
+        SqlValue::Integer(i) => JsValue::from(*i),
+        SqlValue::Real(f) => JsValue::from_f64(*f),
+        SqlValue::Text(s) => JsValue::from_str(s),
+        SqlValue::Blob(bytes) => Uint8Array::from(bytes.as_slice()).buffer().into(),
+    }
+}
+
+/// Largest integer a JS number represents exactly, 2^53 - 1
+const MAX_SAFE_INTEGER: u64 = 9_007_199_254_740_991;
+
+/// Streaming iterator over raw rows, each an array of column values
+pub struct RawRows {
+    cursor: Cursor,
+    done: bool,
+}
+
+impl RawRows {
+    /// The underlying cursor, for reading row counters while streaming
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+}
+
+impl Iterator for RawRows {
+    type Item = Result<Vec<JsValue>>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
         
-        let row = array.get(0);
-        let value: T = serde_wasm_bindgen::from_value(row)
-            .map_err(|e| Error::JsError(format!("Failed to deserialize row: {}", e)))?;
-        Ok(Some(value))
+        match self.cursor.next_raw() {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
     }
 }
 
 // Extension trait for Storage to access SQL
 pub trait SqlStorageExt {
-    fn sql(&self) -> Result<SqlStorage>;
+    fn sql(&self) -> Result<DoSql>;
 }
 
 // Direct binding to DurableObjectStorage which has the sql property
@@ -94,16 +213,49 @@ extern "C" {
     
     #[wasm_bindgen(method, getter, catch)]
     pub fn sql(this: &DurableObjectStorage) -> std::result::Result<SqlStorage, JsValue>;
+
+    // Runs the callback synchronously inside a transaction, rolling back if it throws.
+    // Nested calls become savepoints of the enclosing transaction.
+    #[wasm_bindgen(method, catch, js_name = transactionSync)]
+    pub fn transaction_sync(this: &DurableObjectStorage, callback: &js_sys::Function) -> std::result::Result<JsValue, JsValue>;
+
+    // Point-in-time recovery. Bookmarks are opaque strings naming a state of
+    // the database within the last 30 days.
+    #[wasm_bindgen(method, catch, js_name = getCurrentBookmark)]
+    pub fn get_current_bookmark(this: &DurableObjectStorage) -> std::result::Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch, js_name = getBookmarkForTime)]
+    pub fn get_bookmark_for_time(this: &DurableObjectStorage, timestamp: f64) -> std::result::Result<Promise, JsValue>;
+
+    // Applied when the object next starts. Resolves to a bookmark for the
+    // state just before the restore, so it can be undone.
+    #[wasm_bindgen(method, catch, js_name = onNextSessionRestoreBookmark)]
+    pub fn on_next_session_restore_bookmark(this: &DurableObjectStorage, bookmark: &str) -> std::result::Result<Promise, JsValue>;
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(extends = ::js_sys::Object, typescript_type = "DurableObjectState")]
+    #[derive(Clone)]
+    pub type DurableObjectState;
+
+    // Resets the object: its in-memory state is dropped and the next request starts a new session
+    #[wasm_bindgen(method, catch)]
+    pub fn abort(this: &DurableObjectState, reason: &str) -> std::result::Result<(), JsValue>;
+}
+
+/// The JS object behind a `worker::Storage`
+fn storage_js(storage: &worker::Storage) -> &JsValue {
+    unsafe {
+        // Safety: Storage is a wasm_bindgen type that wraps a JS object
+        &*(storage as *const worker::Storage as *const JsValue)
+    }
 }
 
 // Implement the extension for worker::Storage
 impl SqlStorageExt for worker::Storage {
-    fn sql(&self) -> Result<SqlStorage> {
-        // Convert Storage to JsValue using wasm_bindgen's interop
-        let storage_js: &JsValue = unsafe {
-            // Safety: Storage is a wasm_bindgen type that wraps a JS object
-            &*(self as *const worker::Storage as *const JsValue)
-        };
+    fn sql(&self) -> Result<DoSql> {
+        let storage_js = storage_js(self);
         
         // Access the sql property directly using JS reflection
         let sql_js = js_sys::Reflect::get(storage_js, &JsValue::from_str("sql"))
@@ -112,7 +264,10 @@ impl SqlStorageExt for worker::Storage {
         if sql_js.is_null() || sql_js.is_undefined() {
             Err(Error::RustError("SQL storage not available. Make sure this Durable Object uses new_sqlite_classes in wrangler.toml".to_string()))
         } else {
-            Ok(sql_js.unchecked_into::<SqlStorage>())
+            Ok(DoSql {
+                sql: sql_js.unchecked_into(),
+                storage: storage_js.clone().unchecked_into(),
+            })
         }
     }
 }
@@ -130,257 +285,148 @@ pub fn format_sql_number<T: std::fmt::Display>(value: T) -> String {
     value.to_string()
 }
 
-impl SqlStorage {
-    /// Execute a SQL query and return a cursor over the results
-    pub fn execute(&self, query: &str) -> Result<Cursor> {
-        let cursor = self.exec(query)
+/// SQL handle of a Durable Object: its `SqlStorage` together with the
+/// `DurableObjectStorage` it came from, which runs transactions
+pub struct DoSql {
+    sql: SqlStorage,
+    storage: DurableObjectStorage,
+}
+
+impl SqlBackend for DoSql {
+    type Cursor = Cursor;
+    
+    fn exec_with(&self, query: &str, bindings: &[SqlValue]) -> Result<Cursor> {
+        let values: Box<[JsValue]> = bindings.iter().map(sql_value_to_js).collect();
+        let cursor = self.sql.exec_with_bindings(query, values)
             .map_err(|e| Error::JsError(format!("SQL execution failed: {:?}", e)))?;
         Ok(Cursor::new(cursor))
     }
     
-    /// Prepare a SQL statement for execution with bound parameters (D1-style API)
-    pub fn prepare(&self, query: &str) -> PreparedStatement {
-        PreparedStatement::new(query.to_string(), self)
+    /// Runs through `storage.transactionSync`, since `exec` rejects
+    /// transaction and savepoint statements. Nested calls nest there too.
+    fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&DoSql) -> Result<R>,
+    {
+        transaction_sync(&self.storage, || f(self))
     }
-    
+}
+
+impl DoSql {
     /// Dump the entire database as a binary blob
     pub fn dump_db(&self) -> Result<Vec<u8>> {
-        self.dump()
+        self.sql.dump()
             .map_err(|e| Error::JsError(format!("Failed to dump database: {:?}", e)))
     }
+}
+
+/// Run `f` inside `storage.transactionSync`, throwing into JS on error so the
+/// runtime rolls the transaction back
+fn transaction_sync<R>(storage: &DurableObjectStorage, f: impl FnOnce() -> Result<R>) -> Result<R> {
+    let mut f = Some(f);
+    let mut result = None;
     
-    /// Initialize the database with migrations
-    pub fn migrate(&self, migrations: &[Migration]) -> Result<()> {
-        // Create migrations table if it doesn't exist
-        self.execute(
-            "CREATE TABLE IF NOT EXISTS __migrations (
-                version INTEGER PRIMARY KEY,
-                name TEXT NOT NULL,
-                applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            )"
-        )?;
-        
-        // Get current version
-        let current_version = self
-            .execute("SELECT COALESCE(MAX(version), 0) as version FROM __migrations")?
-            .first::<serde_json::Value>()?
-            .and_then(|v| v.get("version").and_then(|v| v.as_i64()))
-            .unwrap_or(0) as i32;
-        
-        // Apply pending migrations
-        for migration in migrations {
-            if migration.version > current_version {
-                // Execute migration
-                self.execute(migration.sql)?;
-                
-                // Record migration - using direct SQL to avoid the prepare issue
-                let query = format!(
-                    "INSERT INTO __migrations (version, name) VALUES ({}, '{}')",
-                    migration.version,
-                    escape_sql_string(migration.name)
-                );
-                self.execute(&query)?;
+    let outcome = {
+        let mut callback = || -> std::result::Result<(), JsValue> {
+            let Some(f) = f.take() else {
+                return Ok(());
+            };
+            let value = f();
+            let failed = value.is_err();
+            result = Some(value);
+            if failed {
+                Err(JsValue::from_str("transaction rolled back"))
+            } else {
+                Ok(())
             }
-        }
-        
-        Ok(())
-    }
+        };
+        let closure: ScopedClosure<dyn FnMut() -> std::result::Result<(), JsValue>> =
+            ScopedClosure::borrow_mut_aborting(&mut callback);
+        storage.transaction_sync(closure.as_js_value().unchecked_ref())
+    };
     
-    /// Execute multiple SQL statements in a transaction
-    pub fn transaction<F, R>(&self, f: F) -> Result<R>
-    where
-        F: FnOnce(&SqlStorage) -> Result<R>,
-    {
-        self.execute("BEGIN")?;
-        
-        match f(self) {
-            Ok(result) => {
-                self.execute("COMMIT")?;
-                Ok(result)
-            }
-            Err(e) => {
-                let _ = self.execute("ROLLBACK");
-                Err(e)
-            }
-        }
+    match (result, outcome) {
+        // The callback failed: report its error rather than the rollback signal
+        (Some(Err(e)), _) => Err(e),
+        (Some(Ok(value)), Ok(_)) => Ok(value),
+        (_, Err(e)) => Err(Error::JsError(format!("Transaction failed: {:?}", e))),
+        (None, Ok(_)) => Err(Error::RustError("transactionSync did not invoke its callback".into())),
     }
 }
 
-/// Prepared SQL statement that mimics D1's prepare/bind API
-pub struct PreparedStatement<'a> {
-    query: String,
-    sql: &'a SqlStorage,
-    bindings: Vec<JsValue>,
+/// Point-in-time recovery of a SQLite-backed Durable Object's storage
+pub struct Bookmarks {
+    storage: DurableObjectStorage,
 }
 
-impl<'a> PreparedStatement<'a> {
-    fn new(query: String, sql: &'a SqlStorage) -> Self {
+impl Bookmarks {
+    pub fn new(storage: &worker::Storage) -> Self {
         Self {
-            query,
-            sql,
-            bindings: Vec::new(),
+            storage: storage_js(storage).clone().unchecked_into(),
         }
     }
     
-    /// Bind parameters to the prepared statement (D1-style)
-    /// Accepts an array of values to bind to ? placeholders
-    pub fn bind<I, T>(mut self, params: I) -> Self 
-    where
-        I: IntoIterator<Item = T>,
-        T: IntoJsValue,
-    {
-        self.bindings = params.into_iter().map(|v| v.into_js_value()).collect();
-        self
+    /// Bookmark for the database as it is now
+    pub async fn current(&self) -> Result<String> {
+        resolve_bookmark(self.storage.get_current_bookmark(), "getCurrentBookmark").await
     }
     
-    /// Bind a single value (convenience method)
-    pub fn bind_value<T: IntoJsValue>(mut self, value: T) -> Self {
-        self.bindings.push(value.into_js_value());
-        self
+    /// Bookmark for the database as it was at `timestamp_ms`
+    pub async fn for_time(&self, timestamp_ms: i64) -> Result<String> {
+        resolve_bookmark(self.storage.get_bookmark_for_time(timestamp_ms as f64), "getBookmarkForTime").await
     }
     
-    /// Execute the statement and return all rows
-    pub fn all<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
-        let query = self.build_query()?;
-        let cursor = self.sql.exec(&query)
-            .map_err(|e| Error::JsError(format!("SQL execution failed: {:?}", e)))?;
-        Cursor::new(cursor).collect()
+    /// Restore `bookmark` when the object next starts, returning a bookmark
+    /// that undoes the restore
+    pub async fn restore_on_next_session(&self, bookmark: &str) -> Result<String> {
+        resolve_bookmark(self.storage.on_next_session_restore_bookmark(bookmark), "onNextSessionRestoreBookmark").await
     }
-    
-    /// Execute the statement and return the first row
-    pub fn first<T: DeserializeOwned>(&self) -> Result<Option<T>> {
-        let query = self.build_query()?;
-        let cursor = self.sql.exec(&query)
-            .map_err(|e| Error::JsError(format!("SQL execution failed: {:?}", e)))?;
-        Cursor::new(cursor).first()
-    }
-    
-    /// Execute the statement without returning rows (INSERT, UPDATE, DELETE)
-    /// Returns the number of affected rows
-    pub fn run(&self) -> Result<usize> {
-        let query = self.build_query()?;
-        let cursor = self.sql.exec(&query)
-            .map_err(|e| Error::JsError(format!("SQL execution failed: {:?}", e)))?;
-        
-        // For DML statements, the cursor might contain metadata about affected rows
-        // We'll try to extract this information
-        let array = cursor.toArray();
-        Ok(array.length() as usize)
-    }
-    
-    /// Build the final query with parameters substituted
-    fn build_query(&self) -> Result<String> {
-        let mut query = self.query.clone();
-        let mut param_index = 0;
-        
-        // Replace ? placeholders with actual values
-        while let Some(pos) = query.find('?') {
-            if param_index >= self.bindings.len() {
-                return Err(Error::RustError("Not enough parameters bound to query".into()));
-            }
-            
-            let value = &self.bindings[param_index];
-            let replacement = Self::format_value(value)?;
-            
-            query = format!("{}{}{}", &query[..pos], replacement, &query[pos + 1..]);
-            param_index += 1;
-        }
-        
-        // Also handle numbered placeholders like ?1, ?2, etc.
-        for (i, binding) in self.bindings.iter().enumerate() {
-            let placeholder = format!("?{}", i + 1);
-            if query.contains(&placeholder) {
-                let replacement = Self::format_value(binding)?;
-                query = query.replace(&placeholder, &replacement);
-            }
-        }
-        
-        if param_index < self.bindings.len() && !query.contains('?') {
-            return Err(Error::RustError("Too many parameters bound to query".into()));
-        }
-        
-        Ok(query)
-    }
-    
-    /// Format a JavaScript value for SQL
-    fn format_value(value: &JsValue) -> Result<String> {
-        if value.is_null() {
-            Ok("NULL".to_string())
-        } else if value.is_undefined() {
-            Ok("NULL".to_string())
-        } else if let Some(b) = value.as_bool() {
-            Ok(if b { "1".to_string() } else { "0".to_string() })
-        } else if let Some(n) = value.as_f64() {
-            Ok(n.to_string())
-        } else if let Some(s) = value.as_string() {
-            // Escape single quotes in strings
-            Ok(format!("'{}'", s.replace('\'', "''")))
-        } else {
-            // Try to convert to string
-            Ok(format!("'{}'", value.as_string().unwrap_or_default().replace('\'', "''")))
-        }
-    }
-}
-
-/// Simple migration system for SQLite
-pub struct Migration {
-    pub version: i32,
-    pub name: &'static str,
-    pub sql: &'static str,
 }
 
-/// Extension trait for converting Rust types to JsValue for binding
-pub trait IntoJsValue {
-    fn into_js_value(self) -> JsValue;
-}
-
-impl IntoJsValue for &str {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_str(self)
-    }
+async fn resolve_bookmark(promise: std::result::Result<Promise, JsValue>, method: &str) -> Result<String> {
+    let promise = promise.map_err(|e| Error::JsError(format!("{} failed: {:?}", method, e)))?;
+    JsFuture::from(promise).await
+        .map_err(|e| Error::JsError(format!("{} failed: {:?}", method, e)))?
+        .as_string()
+        .ok_or_else(|| Error::RustError(format!("{} did not return a bookmark", method)))
 }
 
-impl IntoJsValue for String {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_str(&self)
+impl DurableObjectState {
+    /// Take the JS state object out of a `State`, returning the `State`
+    /// rebuilt around it and a handle on which `abort` can be called
+    pub fn split(state: worker::State) -> (worker::State, Self) {
+        let inner = state._inner();
+        let object = inner.clone().unchecked_into();
+        (worker::State::from(inner), object)
     }
 }
 
-impl IntoJsValue for &String {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_str(self)
-    }
+/// Reset the Durable Object once `delay` has passed, giving the current
+/// response time to reach the client. The next request starts a new session,
+/// which applies any restore scheduled with `Bookmarks`.
+pub fn reset_after(state: &worker::State, object: &DurableObjectState, delay: Duration, reason: &'static str) {
+    let object = object.clone();
+    state.wait_until(async move {
+        worker::Delay::from(delay).await;
+        // abort() throws by design once the reset is under way
+        let _ = object.abort(reason);
+    });
 }
 
-impl IntoJsValue for i32 {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_f64(self as f64)
+impl PreparedStatement<'_, DoSql> {
+    /// Execute the statement and stream the rows as arrays of column values
+    pub fn raw(&self) -> Result<RawRows> {
+        Ok(self.exec()?.raw())
     }
 }
 
-impl IntoJsValue for i64 {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_f64(self as f64)
-    }
-}
-
-impl IntoJsValue for f64 {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_f64(self)
-    }
+/// Extension trait for converting Rust types to JsValue for binding
+pub trait IntoJsValue {
+    fn into_js_value(self) -> Result<JsValue>;
 }
 
-impl IntoJsValue for bool {
-    fn into_js_value(self) -> JsValue {
-        JsValue::from_bool(self)
+impl<T: IntoSqlValue> IntoJsValue for T {
+    fn into_js_value(self) -> Result<JsValue> {
+        self.into_sql_value().map(|value| sql_value_to_js(&value)).map_err(Error::RustError)
     }
 }
-
-impl<T: IntoJsValue> IntoJsValue for Option<T> {
-    fn into_js_value(self) -> JsValue {
-        match self {
-            Some(value) => value.into_js_value(),
-            None => JsValue::NULL,
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,284 @@
+use worker::Error;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::utils::sql_backend::{ParsedStatement, SqlBackend, SqlCursor};
+use crate::utils::sql_row::SqlValue;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Distinct statements kept parsed. The cache is emptied when it fills up,
+/// which only happens if callers build SQL text dynamically.
+const MAX_CACHED_STATEMENTS: usize = 256;
+/// Distinct statements profiled; later ones are not recorded
+const MAX_PROFILED_STATEMENTS: usize = 256;
+
+/// Parsed statements and optional query profile shared by every `CachedSql`
+/// handle of one Durable Object
+#[derive(Default)]
+pub struct StatementCache {
+    statements: RefCell<HashMap<String, Rc<ParsedStatement>>>,
+    /// `None` while profiling is off
+    profile: RefCell<Option<HashMap<String, QueryProfile>>>,
+}
+
+/// Totals for one statement, keyed by its SQL text
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct QueryProfile {
+    pub calls: u64,
+    /// Time spent in the statement, as its cursors measured it. `None` on
+    /// Durable Objects, whose cursors cannot be timed.
+    pub total_ms: Option<f64>,
+    pub rows_read: u64,
+    pub rows_written: u64,
+}
+
+/// A profiled statement, as reported by `StatementCache::report`
+#[derive(Serialize, Debug)]
+pub struct ProfileEntry {
+    pub sql: String,
+    #[serde(flatten)]
+    pub profile: QueryProfile,
+    pub avg_ms: Option<f64>,
+}
+
+impl StatementCache {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self::default())
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profile.borrow().is_some()
+    }
+
+    /// Turn profiling on or off. Either way the recorded totals are cleared.
+    pub fn set_profiling(&self, enabled: bool) {
+        *self.profile.borrow_mut() = enabled.then(HashMap::new);
+    }
+
+    /// Clear the recorded totals, leaving profiling on or off
+    pub fn reset_profile(&self) {
+        if let Some(profile) = self.profile.borrow_mut().as_mut() {
+            profile.clear();
+        }
+    }
+
+    /// Profiled statements, those that read the most rows in total first,
+    /// then those that wrote the most. Rows are what SQLite reports on every
+    /// backend, where time is not.
+    pub fn report(&self) -> Vec<ProfileEntry> {
+        let mut entries: Vec<ProfileEntry> = self.profile.borrow()
+            .iter()
+            .flatten()
+            .map(|(sql, profile)| ProfileEntry {
+                sql: sql.clone(),
+                profile: profile.clone(),
+                avg_ms: profile.total_ms.map(|total| total / profile.calls.max(1) as f64),
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse((e.profile.rows_read, e.profile.rows_written)));
+        entries
+    }
+
+    fn parse(&self, query: &str) -> Result<Rc<ParsedStatement>> {
+        if let Some(parsed) = self.statements.borrow().get(query) {
+            return Ok(parsed.clone());
+        }
+
+        let parsed = Rc::new(ParsedStatement::parse(query)?);
+        let mut statements = self.statements.borrow_mut();
+        if statements.len() >= MAX_CACHED_STATEMENTS {
+            statements.clear();
+        }
+        statements.insert(query.to_string(), parsed.clone());
+        Ok(parsed)
+    }
+
+    fn record(&self, query: &str, duration_ms: Option<f64>, rows_read: u64, rows_written: u64) {
+        let mut profile = self.profile.borrow_mut();
+        let Some(profile) = profile.as_mut() else { return };
+
+        if !profile.contains_key(query) && profile.len() >= MAX_PROFILED_STATEMENTS {
+            return;
+        }
+        let entry = profile.entry(query.to_string()).or_default();
+        entry.calls += 1;
+        // A statement's total is only meaningful if every call was timed
+        entry.total_ms = match entry.calls {
+            1 => duration_ms,
+            _ => entry.total_ms.zip(duration_ms).map(|(total, ms)| total + ms),
+        };
+        entry.rows_read += rows_read;
+        entry.rows_written += rows_written;
+    }
+}
+
+/// A backend whose prepared statements are parsed once per SQL text, and
+/// whose statements are profiled while the cache has profiling on
+pub struct CachedSql<B> {
+    inner: B,
+    cache: Rc<StatementCache>,
+}
+
+impl<B: SqlBackend> CachedSql<B> {
+    pub fn new(inner: B, cache: Rc<StatementCache>) -> Self {
+        Self { inner, cache }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B: SqlBackend> SqlBackend for CachedSql<B> {
+    type Cursor = ProfiledCursor<B::Cursor>;
+
+    fn exec_with(&self, query: &str, bindings: &[SqlValue]) -> Result<Self::Cursor> {
+        let inner = self.inner.exec_with(query, bindings)?;
+        let profiled = self.cache.profiling().then(|| (self.cache.clone(), query.to_string()));
+        Ok(ProfiledCursor { inner, profiled })
+    }
+
+    fn parse_statement(&self, query: &str) -> Result<Rc<ParsedStatement>> {
+        self.cache.parse(query)
+    }
+
+    /// Not profiled: `run` asks for these after every statement, so they
+    /// would otherwise crowd out the statements being measured
+    fn last_changes(&self) -> Result<(u64, i64)> {
+        self.inner.last_changes()
+    }
+
+    fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Self) -> Result<R>,
+    {
+        // The wrapped handle is the same connection, so the callback can keep
+        // using this one
+        self.inner.transaction(|_| f(self))
+    }
+}
+
+/// Cursor that adds its statement's totals to the profile when dropped,
+/// by which time the counters cover every row that was read
+pub struct ProfiledCursor<C: SqlCursor> {
+    inner: C,
+    profiled: Option<(Rc<StatementCache>, String)>,
+}
+
+impl<C: SqlCursor> SqlCursor for ProfiledCursor<C> {
+    fn column_names(&self) -> Vec<String> {
+        self.inner.column_names()
+    }
+
+    fn column_types(&self) -> Vec<Option<String>> {
+        self.inner.column_types()
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        self.inner.next_row()
+    }
+
+    fn rows_read(&self) -> u64 {
+        self.inner.rows_read()
+    }
+
+    fn rows_written(&self) -> u64 {
+        self.inner.rows_written()
+    }
+
+    fn duration_ms(&self) -> Option<f64> {
+        self.inner.duration_ms()
+    }
+}
+
+impl<C: SqlCursor> Drop for ProfiledCursor<C> {
+    fn drop(&mut self) {
+        if let Some((cache, query)) = self.profiled.take() {
+            cache.record(&query, self.inner.duration_ms(), self.inner.rows_read(), self.inner.rows_written());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    fn database(cache: &Rc<StatementCache>) -> CachedSql<NativeSqlite> {
+        let sql = CachedSql::new(NativeSqlite::open_in_memory().unwrap(), cache.clone());
+        sql.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT)").unwrap();
+        sql
+    }
+
+    #[test]
+    fn parses_each_statement_once() {
+        let cache = StatementCache::new();
+        let sql = database(&cache);
+
+        for name in ["a", "b", "c"] {
+            sql.prepare("INSERT INTO items (name) VALUES (:name)").bind_named("name", name).run().unwrap();
+        }
+        let first = sql.parse_statement("SELECT * FROM items WHERE id = ?").unwrap();
+        let again = sql.parse_statement("SELECT * FROM items WHERE id = ?").unwrap();
+
+        assert!(Rc::ptr_eq(&first, &again));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(sql.prepare("SELECT name FROM items WHERE id = ?").bind_value(2).first::<(String,)>().unwrap(), Some(("b".into(),)));
+    }
+
+    #[test]
+    fn profiles_statements_while_enabled() {
+        let cache = StatementCache::new();
+        let sql = database(&cache);
+        sql.execute("INSERT INTO items (name) VALUES ('untracked')").unwrap();
+
+        cache.set_profiling(true);
+        for name in ["a", "b"] {
+            sql.prepare("INSERT INTO items (name) VALUES (?)").bind_value(name).run().unwrap();
+        }
+        sql.prepare("SELECT * FROM items").all::<(i64, String)>().unwrap();
+
+        let report = cache.report();
+        let profile = |query: &str| report.iter().find(|e| e.sql == query).map(|e| e.profile.clone());
+        let insert = profile("INSERT INTO items (name) VALUES (?)").unwrap();
+        assert_eq!((insert.calls, insert.rows_written), (2, 2));
+        assert!(insert.total_ms.is_some());
+        assert_eq!(profile("SELECT * FROM items").unwrap().calls, 1);
+        // The SELECT read rows, so it ranks above the INSERT that wrote them
+        assert_eq!(report[0].sql, "SELECT * FROM items");
+        assert!(profile("INSERT INTO items (name) VALUES ('untracked')").is_none());
+        assert!(report.iter().all(|e| !e.sql.contains("changes()")), "{:?}", report);
+
+        cache.reset_profile();
+        assert!(cache.profiling() && cache.report().is_empty());
+        sql.prepare("SELECT * FROM items").all::<(i64, String)>().unwrap();
+        assert_eq!(cache.report().len(), 1);
+
+        cache.set_profiling(false);
+        sql.prepare("SELECT * FROM items").all::<(i64, String)>().unwrap();
+        assert!(cache.report().is_empty());
+    }
+
+    #[test]
+    fn transactions_run_through_the_wrapper() {
+        let cache = StatementCache::new();
+        let sql = database(&cache);
+        let result: Result<()> = sql.transaction(|sql| {
+            sql.execute("INSERT INTO items (name) VALUES ('rolled back')")?;
+            Err(Error::RustError("abort".into()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(sql.execute("SELECT COUNT(*) FROM items").unwrap().first::<(i64,)>().unwrap(), Some((0,)));
+    }
+}
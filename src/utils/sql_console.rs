@@ -0,0 +1,361 @@
+use worker::Error;
+use serde::Serialize;
+use serde_json::Value;
+use crate::utils::sql_backend::{SqlBackend, SqlCursor};
+use crate::utils::sql_export::json_value;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Keywords that start or contain a statement that changes the database or
+/// the connection. Used as a function name (followed by `(`), `replace` is
+/// still allowed.
+const WRITE_KEYWORDS: &[&str] = &[
+    "ALTER", "ANALYZE", "ATTACH", "BEGIN", "COMMIT", "CREATE", "DELETE", "DETACH", "DROP",
+    "INSERT", "PRAGMA", "REINDEX", "RELEASE", "REPLACE", "ROLLBACK", "SAVEPOINT", "UPDATE", "VACUUM",
+];
+
+/// Limits for a console query
+///
+/// `max_rows` is enforced by SQLite itself, as a LIMIT around the query.
+/// `max_rows_read` can only be checked between rows, as SQLite cannot be
+/// stopped partway through producing one, so it fails a query after the
+/// fact rather than bounding it. What bounds the work done for a single row
+/// is `run_read_only` refusing recursive CTEs and query plans that scan a
+/// table once for every row of another.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    /// Rows returned before the result is truncated
+    pub max_rows: usize,
+    /// Rows SQLite may read, as the cursor reports them after each row,
+    /// before the query fails
+    pub max_rows_read: u64,
+}
+
+/// Result of a read-only console query
+#[derive(Serialize, Debug)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub column_types: Vec<Option<String>>,
+    pub rows: Vec<Vec<Value>>,
+    /// More rows were available than `max_rows`
+    pub truncated: bool,
+    pub rows_read: u64,
+}
+
+/// Check that `query` is a single statement that can only read: it must
+/// start with SELECT, WITH or VALUES, contain no write keywords outside of
+/// string literals, quoted identifiers and comments, and balance its
+/// parentheses. Recursive CTEs, which can run without bound before
+/// returning a row, are rejected too.
+///
+/// Returns the statement without its terminating semicolon, ready to be
+/// nested in another.
+pub fn validate_read_only(query: &str) -> std::result::Result<&str, String> {
+    let (words, statement) = words(query)?;
+    match words.first().map(|(word, _)| word.as_str()) {
+        Some("SELECT" | "WITH" | "VALUES") => {}
+        Some(other) => return Err(format!("Only SELECT statements are allowed, not {}", other)),
+        None => return Err("Query is empty".to_string()),
+    }
+    if let Some((word, _)) = words.iter().find(|(word, call)| !call && WRITE_KEYWORDS.contains(&word.as_str())) {
+        return Err(format!("{} is not allowed in a read-only query", word));
+    }
+    if words.iter().any(|(word, call)| !call && word == "RECURSIVE") {
+        return Err("Recursive queries are not allowed".to_string());
+    }
+    Ok(statement)
+}
+
+/// Reject `query` if its plan has a nested loop that scans a whole table
+/// for every row of an outer one. That is what a join without a usable
+/// constraint becomes, however it is written (a comma or CROSS join, `ON
+/// 1=1`, a WHERE clause that compares nothing), and it can read the
+/// product of the tables' sizes before returning a row.
+fn check_plan<B: SqlBackend>(sql: &B, query: &str) -> Result<()> {
+    // Rows of EXPLAIN QUERY PLAN are (id, parent, notused, detail); the
+    // loops of one SELECT share a parent, outermost first
+    let plan = sql.execute(&format!("EXPLAIN QUERY PLAN {}", query))?.collect::<(i64, i64, i64, String)>()?;
+    let scans: Vec<(i64, &str)> = plan.iter()
+        .filter_map(|(_, parent, _, detail)| {
+            let table = detail.strip_prefix("SCAN ")?;
+            (table != "CONSTANT ROW").then(|| (*parent, table.split(' ').next().unwrap_or(table)))
+        })
+        .collect();
+    for (i, (parent, inner)) in scans.iter().enumerate() {
+        if let Some((_, outer)) = scans[..i].iter().find(|(p, _)| p == parent) {
+            return Err(Error::RustError(format!(
+                "Query would scan {} once for every row of {}; join them on an indexed column", inner, outer
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// An unquoted word, uppercased, flagged when immediately followed by `(`
+type Word = (String, bool);
+
+/// Words of a single statement, outside literals, quoted identifiers and
+/// comments, and the statement up to its terminating semicolon
+fn words(query: &str) -> std::result::Result<(Vec<Word>, &str), String> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut words = Vec::new();
+    let mut end = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let close = match c {
+            '\'' | '"' | '`' => Some(c),
+            '[' => Some(']'),
+            _ => None,
+        };
+
+        if let Some(close) = close {
+            // Doubled quotes escape the quote character
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("Unterminated quoted string or identifier".to_string()),
+                    Some(&q) if q == close && chars.get(i + 1) == Some(&close) && close != ']' => i += 2,
+                    Some(&q) if q == close => break,
+                    Some(_) => i += 1,
+                }
+            }
+            i += 1;
+            continue;
+        }
+
+        if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if end.is_some() {
+            return Err("Only a single statement is allowed".to_string());
+        }
+        if c == ';' {
+            end = Some(chars[..i].iter().map(|c| c.len_utf8()).sum());
+            i += 1;
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect::<String>().to_ascii_uppercase();
+            let call = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&'(');
+            words.push((word, call));
+            continue;
+        }
+
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1).ok_or("Unbalanced parentheses")?,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if depth > 0 {
+        return Err("Unbalanced parentheses".to_string());
+    }
+    Ok((words, &query[..end.unwrap_or(query.len())]))
+}
+
+/// Run a read-only query, returning at most `limits.max_rows` rows and
+/// failing once it has read more than `limits.max_rows_read` rows. The
+/// query runs as `SELECT * FROM (query) LIMIT max_rows + 1`, so SQLite
+/// stops producing rows once the result is known to be truncated, and only
+/// if `check_plan` accepts that statement.
+///
+/// Two checks keep the console from changing the database, and neither
+/// depends on the limits:
+///
+/// - `validate_read_only` accepts only a single SELECT, WITH or VALUES
+///   statement with no write keyword outside literals and comments.
+/// - The query runs in a transaction that is rolled back with an error if
+///   the cursor reports any row written. This catches whatever the keyword
+///   check misses, such as a data-modifying CTE or a RETURNING clause.
+pub fn run_read_only<B: SqlBackend>(sql: &B, query: &str, limits: QueryLimits) -> Result<QueryResult> {
+    let statement = validate_read_only(query).map_err(Error::RustError)?;
+    // Newlines end any trailing line comment; balanced parentheses keep
+    // the LIMIT outside the query
+    let limited = format!("SELECT * FROM (\n{}\n) LIMIT {}", statement, limits.max_rows + 1);
+    check_plan(sql, &limited)?;
+    run_rolled_back(sql, &limited, limits)
+}
+
+/// Run `query` within `limits` in a transaction that rolls back if it wrote
+fn run_rolled_back<B: SqlBackend>(sql: &B, query: &str, limits: QueryLimits) -> Result<QueryResult> {
+    sql.transaction(|sql| {
+        let mut cursor = sql.execute(query)?;
+        let mut rows = Vec::new();
+        let mut truncated = false;
+
+        loop {
+            let next = cursor.next_row()?;
+            if cursor.rows_read() > limits.max_rows_read {
+                return Err(Error::RustError(format!("Query read more than {} rows", limits.max_rows_read)));
+            }
+            let Some(values) = next else { break };
+            if rows.len() == limits.max_rows {
+                truncated = true;
+                break;
+            }
+            rows.push(values.iter().map(json_value).collect());
+        }
+
+        if cursor.rows_written() > 0 {
+            return Err(Error::RustError("Query attempted to write to the database".to_string()));
+        }
+
+        Ok(QueryResult {
+            columns: cursor.column_names(),
+            column_types: cursor.column_types(),
+            rows,
+            truncated,
+            rows_read: cursor.rows_read(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    const LIMITS: QueryLimits = QueryLimits { max_rows: 2, max_rows_read: 1000 };
+
+    fn database() -> NativeSqlite {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT, data BLOB);
+                     INSERT INTO notes (body, data) VALUES ('one', X'00FF'), ('two', NULL), ('three', NULL);").unwrap();
+        sql
+    }
+
+    fn run(sql: &NativeSqlite, query: &str) -> Result<QueryResult> {
+        run_read_only(sql, query, LIMITS)
+    }
+
+    fn count(sql: &NativeSqlite) -> i64 {
+        sql.execute("SELECT COUNT(*) FROM notes").unwrap().first::<(i64,)>().unwrap().unwrap().0
+    }
+
+    #[test]
+    fn accepts_only_single_read_statements() {
+        assert_eq!(validate_read_only("SELECT * FROM notes; -- done"), Ok("SELECT * FROM notes"));
+        assert!(validate_read_only("SELECT CASE WHEN id > 1 THEN body END FROM notes").is_ok());
+        assert!(validate_read_only("with t AS (SELECT 1 AS n) SELECT replace(body, 'o', '0') FROM notes JOIN t ON t.n = notes.id").is_ok());
+        assert!(validate_read_only("SELECT 'DELETE FROM notes', \"update\" -- DROP TABLE notes\nFROM notes /* INSERT */").is_ok());
+
+        assert!(validate_read_only("").is_err());
+        assert!(validate_read_only("DELETE FROM notes").is_err());
+        assert!(validate_read_only("WITH t AS (SELECT 1) DELETE FROM notes").is_err());
+        assert!(validate_read_only("SELECT 1; DROP TABLE notes").is_err());
+        assert!(validate_read_only("SELECT 1; SELECT 2").is_err());
+        assert!(validate_read_only("PRAGMA table_info(notes)").is_err());
+        assert!(validate_read_only("SELECT 'unterminated").is_err());
+        assert!(validate_read_only("SELECT 1) LIMIT 1000 UNION SELECT (1").is_err());
+    }
+
+    #[test]
+    fn rejects_recursion_and_unconstrained_joins() {
+        let sql = database();
+        assert!(validate_read_only("WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n) SELECT count(*) FROM n").is_err());
+
+        for query in [
+            "SELECT substr(n.body, 1, 2), n.id FROM notes n LEFT JOIN notes m USING (id) WHERE n.id IN (1, 2)",
+            "SELECT * FROM notes NATURAL LEFT JOIN (SELECT id, body FROM notes) x JOIN notes y ON (x.id = y.id)",
+            "SELECT (SELECT count(*) FROM notes), 1 FROM notes",
+            "SELECT a.body FROM notes a, notes b WHERE a.id = b.id",
+        ] {
+            assert!(run(&sql, query).is_ok(), "{} was refused", query);
+        }
+        for query in [
+            "SELECT count(*) FROM notes a, notes b, notes c",
+            "SELECT count(*) FROM notes a CROSS JOIN notes b",
+            "SELECT count(*) FROM notes a JOIN notes b ON 1 = 1",
+            "SELECT count(*) FROM notes a, notes b WHERE 1",
+            "SELECT count(*) FROM notes a JOIN notes b JOIN notes c ON a.id = c.id",
+            "SELECT * FROM notes WHERE id IN (SELECT a.id FROM notes a JOIN notes b)",
+            "SELECT * FROM notes JOIN (SELECT notes.id FROM notes, json_each('[1]')) x ON (x.id = notes.id)",
+        ] {
+            let error = run(&sql, query).unwrap_err();
+            assert!(error.to_string().contains("once for every row"), "{}: {}", query, error);
+        }
+    }
+
+    #[test]
+    fn returns_columns_types_and_limited_rows() {
+        let sql = database();
+        let result = run(&sql, "SELECT id, body, data, length(body) AS size FROM notes ORDER BY id").unwrap();
+
+        assert_eq!(result.columns, ["id", "body", "data", "size"]);
+        assert_eq!(result.column_types, [Some("INTEGER".into()), Some("TEXT".into()), Some("BLOB".into()), None]);
+        assert_eq!(result.rows.len(), 2);
+        assert!(result.truncated);
+        assert_eq!(result.rows[0], [Value::from(1), Value::from("one"), Value::from("AP8="), Value::from(3)]);
+
+        assert!(!run(&sql, "SELECT * FROM notes WHERE id = 3").unwrap().truncated);
+        assert!(run(&sql, "UPDATE notes SET body = 'x'").is_err());
+    }
+
+    #[test]
+    fn stops_after_the_rows_read_budget() {
+        let sql = database();
+        // One row comes back, but it takes a scan of the whole table
+        let limits = QueryLimits { max_rows: 10, max_rows_read: 1 };
+        let result = run_read_only(&sql, "SELECT max(body) FROM notes", limits);
+        assert!(result.unwrap_err().to_string().contains("read more than 1 rows"));
+        assert!(run_read_only(&sql, "SELECT body FROM notes WHERE id = 1", limits).is_ok());
+    }
+
+    #[test]
+    fn limits_rows_within_sqlite() {
+        let sql = database();
+        sql.execute("WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 1000)
+                     INSERT INTO notes (body) SELECT 'row ' || x FROM n").unwrap();
+
+        // SQLite stops after the row that shows the result is truncated,
+        // rather than scanning the other thousand
+        let result = run(&sql, "SELECT body FROM notes -- trailing comment").unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert!(result.truncated);
+        assert!(result.rows_read <= 3, "read {} rows", result.rows_read);
+    }
+
+    #[test]
+    fn rolls_back_writes_that_get_past_validation() {
+        let sql = database();
+        for query in [
+            "INSERT INTO notes (body) VALUES ('four') RETURNING id",
+            "WITH t AS (SELECT 'four' AS body) INSERT INTO notes (body) SELECT body FROM t",
+            "UPDATE notes SET body = 'x' RETURNING id",
+        ] {
+            assert!(validate_read_only(query).is_err(), "{} passed validation", query);
+            let error = run_rolled_back(&sql, query, LIMITS).unwrap_err();
+            assert!(error.to_string().contains("attempted to write"), "{}: {}", query, error);
+        }
+        assert_eq!(count(&sql), 3);
+        assert_eq!(run(&sql, "SELECT body FROM notes WHERE id = 1").unwrap().rows[0], [Value::from("one")]);
+    }
+}
@@ -0,0 +1,205 @@
+use worker::Error;
+use serde::Deserialize;
+use std::fmt::Write;
+use crate::utils::sql_backend::{SqlBackend, SqlCursor};
+use crate::utils::sql_row::SqlValue;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Write the database out as SQL statements that recreate it.
+///
+/// The script can be replayed into a database that already has the schema:
+/// objects are created with `IF NOT EXISTS` and each table is emptied before
+/// its rows are inserted. Indexes, views and triggers come after the data,
+/// and foreign key checks are deferred to the end of the importing
+/// transaction so tables can be loaded in any order.
+///
+/// Virtual tables are recreated but their rows are not dumped; FTS5 indexes
+/// over an external content table are rebuilt from it at the end.
+pub(crate) fn dump_sql<B: SqlBackend>(sql: &B) -> Result<String> {
+    #[derive(Deserialize)]
+    struct SchemaObject {
+        #[serde(rename = "type")]
+        kind: String,
+        name: String,
+        sql: Option<String>,
+    }
+
+    let objects = sql.execute(
+        "SELECT type, name, sql FROM sqlite_master
+         WHERE sql IS NOT NULL
+         AND name NOT LIKE 'sqlite_%'
+         AND name NOT LIKE '\\_cf\\_%' ESCAPE '\\'
+         ORDER BY CASE type WHEN 'table' THEN 0 WHEN 'index' THEN 1 WHEN 'view' THEN 2 ELSE 3 END, rowid"
+    )?.collect::<SchemaObject>()?;
+
+    // Virtual tables keep their data in shadow tables that they create themselves
+    let virtual_tables: Vec<&str> = objects.iter()
+        .filter(|o| o.sql.as_deref().is_some_and(|s| s.starts_with("CREATE VIRTUAL TABLE ")))
+        .map(|o| o.name.as_str())
+        .collect();
+    let is_shadow = |name: &str| {
+        virtual_tables.iter().any(|v| name.len() > v.len() + 1 && name.starts_with(v) && name.as_bytes()[v.len()] == b'_')
+    };
+
+    let mut out = String::from("-- SQLite dump\nPRAGMA defer_foreign_keys = ON;\n");
+
+    for object in objects.iter().filter(|o| !is_shadow(&o.name)) {
+        let Some(create) = &object.sql else { continue };
+        let _ = writeln!(out, "{};", if_not_exists(create));
+
+        if object.kind != "table" || virtual_tables.contains(&object.name.as_str()) {
+            continue;
+        }
+
+        let table = quote_identifier(&object.name);
+        let _ = writeln!(out, "DELETE FROM {};", table);
+
+        let mut cursor = sql.execute(&format!("SELECT * FROM {}", table))?;
+        let columns = cursor.column_names()
+            .iter()
+            .map(|c| quote_identifier(c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        while let Some(values) = cursor.next_row()? {
+            let values = values.iter().map(sql_literal).collect::<Vec<_>>().join(", ");
+            let _ = writeln!(out, "INSERT INTO {} ({}) VALUES ({});", table, columns, values);
+        }
+    }
+
+    for object in objects.iter().filter(|o| virtual_tables.contains(&o.name.as_str())) {
+        let create = object.sql.as_deref().unwrap_or_default().to_ascii_lowercase();
+        if create.contains("using fts5") && create.contains("content=") && !create.contains("content=''") {
+            let table = quote_identifier(&object.name);
+            let _ = writeln!(out, "INSERT INTO {0}({0}) VALUES ('rebuild');", table);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Replay a SQL script inside a single transaction. Nothing is applied if any
+/// statement fails.
+pub(crate) fn import_sql<B: SqlBackend>(sql: &B, script: &str) -> Result<u64> {
+    sql.transaction(|sql| {
+        let mut cursor = sql.execute(script)?;
+        cursor.drain()?;
+        Ok(cursor.rows_written())
+    })
+}
+
+/// sqlite_master stores CREATE statements normalized, with any
+/// `IF NOT EXISTS` removed, so the prefix can be rewritten directly
+fn if_not_exists(create: &str) -> String {
+    const PREFIXES: [&str; 6] = [
+        "CREATE TABLE ",
+        "CREATE VIRTUAL TABLE ",
+        "CREATE INDEX ",
+        "CREATE UNIQUE INDEX ",
+        "CREATE VIEW ",
+        "CREATE TRIGGER ",
+    ];
+    match PREFIXES.iter().find(|p| create.starts_with(*p)) {
+        Some(prefix) => format!("{}IF NOT EXISTS {}", prefix, &create[prefix.len()..]),
+        None => create.to_string(),
+    }
+}
+
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Render a value as a SQL literal that reads back as the same value
+pub fn sql_literal(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null => "NULL".to_string(),
+        SqlValue::Integer(i) => i.to_string(),
+        SqlValue::Real(f) if f.is_infinite() => if *f > 0.0 { "9e999" } else { "-9e999" }.to_string(),
+        // {:?} keeps a decimal point on whole numbers so the value stays REAL
+        SqlValue::Real(f) => format!("{:?}", f),
+        SqlValue::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        SqlValue::Blob(bytes) => {
+            let mut hex = String::with_capacity(bytes.len() * 2 + 3);
+            hex.push_str("X'");
+            for byte in bytes {
+                let _ = write!(hex, "{:02X}", byte);
+            }
+            hex.push('\'');
+            hex
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+    use crate::utils::sql_row::Row;
+
+    const SCHEMA: &str = "
+        CREATE TABLE authors (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL);
+        CREATE TABLE \"odd \"\"name\"\"\" (author_id INTEGER REFERENCES authors (id), score REAL, data BLOB, note TEXT);
+        CREATE INDEX idx_note ON \"odd \"\"name\"\"\" (note);
+        CREATE VIEW author_names AS SELECT name FROM authors;
+    ";
+
+    fn source() -> NativeSqlite {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute(SCHEMA).unwrap();
+        sql.execute("INSERT INTO authors (name) VALUES ('O''Brien'), ('multi\nline')").unwrap();
+        sql.prepare("INSERT INTO \"odd \"\"name\"\"\" VALUES (?, ?, ?, ?)")
+            .bind_value(2)
+            .bind_value(2.0)
+            .bind_value(SqlValue::Blob(vec![0, 255, 16]))
+            .bind_value(None::<&str>)
+            .run()
+            .unwrap();
+        sql
+    }
+
+    fn contents(sql: &NativeSqlite) -> Vec<Vec<SqlValue>> {
+        let mut rows: Vec<Vec<SqlValue>> = sql.execute("SELECT * FROM authors ORDER BY id").unwrap()
+            .collect::<Row>().unwrap()
+            .into_iter()
+            .map(Row::into_values)
+            .collect();
+        rows.extend(sql.execute("SELECT *, typeof(score) FROM \"odd \"\"name\"\"\"").unwrap()
+            .collect::<Row>().unwrap()
+            .into_iter()
+            .map(Row::into_values));
+        rows
+    }
+
+    #[test]
+    fn dump_round_trips_into_an_empty_database() {
+        let original = source();
+        let dump = original.dump_sql().unwrap();
+        assert!(dump.contains("CREATE INDEX IF NOT EXISTS idx_note"));
+        assert!(dump.contains("X'00FF10'"));
+
+        let restored = NativeSqlite::open_in_memory().unwrap();
+        restored.import_sql(&dump).unwrap();
+        assert_eq!(contents(&restored), contents(&original));
+        assert!(restored.execute("SELECT * FROM author_names").is_ok());
+    }
+
+    #[test]
+    fn import_replaces_rows_in_an_existing_schema() {
+        let target = NativeSqlite::open_in_memory().unwrap();
+        target.execute(SCHEMA).unwrap();
+        target.execute("INSERT INTO authors (name) VALUES ('stale')").unwrap();
+
+        let original = source();
+        target.import_sql(&original.dump_sql().unwrap()).unwrap();
+        assert_eq!(contents(&target), contents(&original));
+    }
+
+    #[test]
+    fn failed_import_changes_nothing() {
+        let target = source();
+        let before = contents(&target);
+        let result = target.import_sql("DELETE FROM authors; INSERT INTO missing VALUES (1);");
+        assert!(result.is_err());
+        assert_eq!(contents(&target), before);
+    }
+}
@@ -0,0 +1,255 @@
+use worker::Error;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Map, Number, Value};
+use crate::utils::sql_row::{Row, SqlValue};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Rows are encoded into chunks of roughly this many bytes, so a response
+/// body stream holds at most one chunk at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Tabular formats a result set can be exported as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// RFC 4180 CSV with a header row
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+    /// A single JSON array of objects
+    Json,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "csv" => Some(ExportFormat::Csv),
+            "ndjson" | "jsonl" => Some(ExportFormat::Ndjson),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Encodes a stream of rows into byte chunks in an `ExportFormat`
+///
+/// Rows are pulled from the underlying iterator only as chunks are
+/// requested, so a streaming cursor is read no faster than the response
+/// body is consumed.
+pub struct ExportChunks<I> {
+    format: ExportFormat,
+    columns: Vec<String>,
+    rows: I,
+    started: bool,
+    wrote_row: bool,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = Result<Row>>> ExportChunks<I> {
+    pub fn new(format: ExportFormat, columns: Vec<String>, rows: I) -> Self {
+        Self {
+            format,
+            columns,
+            rows,
+            started: false,
+            wrote_row: false,
+            finished: false,
+        }
+    }
+
+    fn write_header(&self, out: &mut String) {
+        match self.format {
+            ExportFormat::Csv => {
+                let header: Vec<String> = self.columns.iter().map(|c| csv_field(c)).collect();
+                out.push_str(&header.join(","));
+                out.push_str("\r\n");
+            }
+            ExportFormat::Ndjson => {}
+            ExportFormat::Json => out.push('['),
+        }
+    }
+
+    fn write_row(&mut self, row: &Row, out: &mut String) {
+        match self.format {
+            ExportFormat::Csv => {
+                let fields: Vec<String> = row.values().iter().map(csv_value).collect();
+                out.push_str(&fields.join(","));
+                out.push_str("\r\n");
+            }
+            ExportFormat::Ndjson => {
+                out.push_str(&json_object(&self.columns, row).to_string());
+                out.push('\n');
+            }
+            ExportFormat::Json => {
+                if self.wrote_row {
+                    out.push(',');
+                }
+                out.push('\n');
+                out.push_str(&json_object(&self.columns, row).to_string());
+            }
+        }
+        self.wrote_row = true;
+    }
+
+    fn write_footer(&self, out: &mut String) {
+        if self.format == ExportFormat::Json {
+            out.push_str(if self.wrote_row { "\n]\n" } else { "]\n" });
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Row>>> Iterator for ExportChunks<I> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let mut out = String::new();
+        if !self.started {
+            self.started = true;
+            self.write_header(&mut out);
+        }
+
+        while out.len() < CHUNK_SIZE {
+            match self.rows.next() {
+                Some(Ok(row)) => self.write_row(&row, &mut out),
+                Some(Err(e)) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+                None => {
+                    self.finished = true;
+                    self.write_footer(&mut out);
+                    break;
+                }
+            }
+        }
+
+        if out.is_empty() {
+            None
+        } else {
+            Some(Ok(out.into_bytes()))
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) || field.starts_with(' ') || field.ends_with(' ') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// NULL is written as an empty field and BLOBs as base64
+fn csv_value(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null => String::new(),
+        SqlValue::Integer(i) => i.to_string(),
+        SqlValue::Real(f) => f.to_string(),
+        SqlValue::Text(s) => csv_field(s),
+        SqlValue::Blob(bytes) => STANDARD.encode(bytes),
+    }
+}
+
+fn json_object(columns: &[String], row: &Row) -> Value {
+    let object: Map<String, Value> = columns.iter()
+        .cloned()
+        .zip(row.values().iter().map(json_value))
+        .collect();
+    Value::Object(object)
+}
+
+/// BLOBs are written as base64 strings; non-finite reals as null
+pub(crate) fn json_value(value: &SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(i) => Value::from(*i),
+        SqlValue::Real(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        SqlValue::Text(s) => Value::String(s.clone()),
+        SqlValue::Blob(bytes) => Value::String(STANDARD.encode(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn rows(values: Vec<Vec<SqlValue>>) -> (Vec<String>, impl Iterator<Item = Result<Row>>) {
+        let columns: Rc<[String]> = vec!["id".to_string(), "note".to_string()].into();
+        let names = columns.to_vec();
+        (names, values.into_iter().map(move |v| Ok(Row::new(columns.clone(), v))))
+    }
+
+    fn export(format: ExportFormat, values: Vec<Vec<SqlValue>>) -> String {
+        let (columns, rows) = rows(values);
+        let chunks: Result<Vec<Vec<u8>>> = ExportChunks::new(format, columns, rows).collect();
+        String::from_utf8(chunks.unwrap().concat()).unwrap()
+    }
+
+    fn sample() -> Vec<Vec<SqlValue>> {
+        vec![
+            vec![SqlValue::Integer(1), SqlValue::Text("plain".into())],
+            vec![SqlValue::Integer(2), SqlValue::Text("a, \"quoted\"\nline".into())],
+            vec![SqlValue::Real(2.5), SqlValue::Null],
+        ]
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        assert_eq!(
+            export(ExportFormat::Csv, sample()),
+            "id,note\r\n1,plain\r\n2,\"a, \"\"quoted\"\"\nline\"\r\n2.5,\r\n"
+        );
+        assert_eq!(export(ExportFormat::Csv, vec![]), "id,note\r\n");
+    }
+
+    #[test]
+    fn json_formats_write_objects() {
+        assert_eq!(
+            export(ExportFormat::Ndjson, sample()),
+            "{\"id\":1,\"note\":\"plain\"}\n{\"id\":2,\"note\":\"a, \\\"quoted\\\"\\nline\"}\n{\"id\":2.5,\"note\":null}\n"
+        );
+
+        let json: Value = serde_json::from_str(&export(ExportFormat::Json, sample())).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_eq!(json[1]["note"], "a, \"quoted\"\nline");
+        assert_eq!(export(ExportFormat::Json, vec![]), "[]\n");
+    }
+
+    #[test]
+    fn large_exports_are_split_into_chunks() {
+        let note = "x".repeat(1000);
+        let values = (0..200).map(|i| vec![SqlValue::Integer(i), SqlValue::Text(note.clone())]).collect();
+        let (columns, rows) = rows(values);
+        let chunks: Vec<Vec<u8>> = ExportChunks::new(ExportFormat::Json, columns, rows)
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() < CHUNK_SIZE + 2048));
+        let json: Value = serde_json::from_slice(&chunks.concat()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 200);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::utils::sql_bindings::format_sql_value;
+use crate::utils::sql_dump::quote_identifier;
+use crate::utils::sql_row::{IntoSqlValue, SqlValue};
+use serde::de::{self, DeserializeOwned, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A value kept in a TEXT column as JSON: bound as its JSON text and parsed
+/// back when the column is read. It serializes as the inner value, so API
+/// responses carry the JSON itself rather than a string of it.
+///
+/// Values that cannot be written as JSON, such as maps with non-string
+/// keys, bind as NULL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoSqlValue for Json<T> {
+    fn into_sql_value(self) -> Result<SqlValue, String> {
+        (&self).into_sql_value()
+    }
+}
+
+impl<T: Serialize> IntoSqlValue for &Json<T> {
+    fn into_sql_value(self) -> Result<SqlValue, String> {
+        Ok(serde_json::to_string(&self.0).map(SqlValue::Text).unwrap_or(SqlValue::Null))
+    }
+}
+
+impl<T: Serialize> Serialize for Json<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Json<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(JsonText(PhantomData))
+    }
+}
+
+struct JsonText<T>(PhantomData<T>);
+
+impl<T: DeserializeOwned> Visitor<'_> for JsonText<T> {
+    type Value = Json<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("JSON text")
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<Json<T>, E> {
+        serde_json::from_str(text).map(Json).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Json<T>, E> {
+        serde_json::from_slice(bytes).map(Json).map_err(E::custom)
+    }
+}
+
+/// A path into a JSON value, such as `$.tags[0]`, built from keys and array
+/// indexes. Keys that are not plain identifiers are quoted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath(String);
+
+impl JsonPath {
+    /// The whole value, `$`
+    pub fn root() -> Self {
+        JsonPath("$".to_string())
+    }
+
+    /// The member `key` of an object. SQLite paths cannot escape a double
+    /// quote, so keys containing one never match.
+    pub fn key(mut self, key: &str) -> Self {
+        let plain = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if plain {
+            self.0.push('.');
+            self.0.push_str(key);
+        } else {
+            self.0.push_str(&format!(".\"{}\"", key));
+        }
+        self
+    }
+
+    /// The element at `index` of an array
+    pub fn index(mut self, index: usize) -> Self {
+        self.0.push_str(&format!("[{}]", index));
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl IntoSqlValue for &JsonPath {
+    fn into_sql_value(self) -> Result<SqlValue, String> {
+        Ok(SqlValue::Text(self.0.clone()))
+    }
+}
+
+/// A column name, optionally qualified as `table.column`, quoted part by part
+fn quote_column(column: &str) -> String {
+    column.split('.').map(quote_identifier).collect::<Vec<_>>().join(".")
+}
+
+/// `json_extract(column, 'path')`: the SQL value at `path` in a JSON column,
+/// or NULL when there is none
+pub fn json_extract(column: &str, path: &JsonPath) -> String {
+    format!("json_extract({}, {})", quote_column(column), format_sql_value(path.as_str()))
+}
+
+/// `json_each(column, 'path')`: a table-valued function with one row per
+/// element of the array (or member of the object) at `path`, exposing `key`,
+/// `value` and `type` columns
+pub fn json_each(column: &str, path: &JsonPath) -> String {
+    format!("json_each({}, {})", quote_column(column), format_sql_value(path.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_backend::SqlBackend;
+    use crate::utils::sql_native::NativeSqlite;
+    use serde_json::{json, Value};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Settings {
+        theme: String,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn builds_quoted_paths() {
+        assert_eq!(JsonPath::root().key("tags").index(1).as_str(), "$.tags[1]");
+        assert_eq!(JsonPath::root().key("two words").key("_ok").as_str(), "$.\"two words\"._ok");
+        assert_eq!(json_extract("metadata", &JsonPath::root().key("it's")), "json_extract(\"metadata\", '$.\"it''s\"')");
+    }
+
+    #[test]
+    fn stores_json_as_text_and_queries_it() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute("CREATE TABLE prefs (id INTEGER PRIMARY KEY, settings TEXT)").unwrap();
+
+        let settings = Settings { theme: "dark".into(), tags: vec!["a".into(), "b c".into()] };
+        sql.prepare("INSERT INTO prefs (settings) VALUES (?), (?)")
+            .bind_value(Json(&settings))
+            .bind_value(None::<Json<Value>>)
+            .run()
+            .unwrap();
+
+        assert_eq!(sql.prepare("SELECT typeof(settings) FROM prefs WHERE id = 1").first::<(String,)>().unwrap(), Some(("text".into(),)));
+
+        let stored = sql.prepare("SELECT settings FROM prefs ORDER BY id").all::<(Option<Json<Settings>>,)>().unwrap();
+        assert_eq!(stored, [(Some(Json(settings)),), (None,)]);
+
+        let theme = sql.prepare(&format!("SELECT {} FROM prefs WHERE id = 1", json_extract("settings", &JsonPath::root().key("theme"))))
+            .first::<(String,)>()
+            .unwrap();
+        assert_eq!(theme, Some(("dark".into(),)));
+
+        let tags = sql.prepare(&format!("SELECT value FROM prefs, {} WHERE prefs.id = ? ORDER BY key", json_each("prefs.settings", &JsonPath::root().key("tags"))))
+            .bind_value(1)
+            .all::<(String,)>()
+            .unwrap();
+        assert_eq!(tags, [("a".into(),), ("b c".into(),)]);
+
+        let value: Json<Value> = sql.prepare("SELECT ?").bind_value(Json(json!({"n": 1}))).first::<(Json<Value>,)>().unwrap().unwrap().0;
+        assert_eq!(value.0, json!({"n": 1}));
+    }
+}
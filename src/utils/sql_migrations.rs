@@ -0,0 +1,298 @@
+use worker::Error;
+use serde::{Deserialize, Serialize};
+use crate::utils::sql_backend::{SqlBackend, SqlCursor};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Simple migration system for SQLite
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Script that reverts `sql`, if the migration is reversible
+    pub down: Option<&'static str>,
+}
+
+impl Migration {
+    /// FNV-1a hash of the up script, stored when the migration is applied
+    /// so later edits to an applied migration are detected
+    pub fn checksum(&self) -> String {
+        let hash = self.sql.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("{:016x}", hash)
+    }
+}
+
+/// State of a single migration, as reported by `SqlBackend::migration_status`
+#[derive(Serialize, Debug)]
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: &'static str,
+    pub applied: bool,
+    pub applied_at: Option<String>,
+    pub checksum: String,
+    /// `None` when the migration has not been applied
+    pub checksum_matches: Option<bool>,
+    pub reversible: bool,
+}
+
+/// A row of the `__migrations` bookkeeping table
+#[derive(Deserialize)]
+struct AppliedMigration {
+    version: i32,
+    name: String,
+    checksum: Option<String>,
+    applied_at: Option<String>,
+}
+
+/// Migrate the schema up or down to `target` (0 reverts everything).
+///
+/// Applied migrations are verified against their stored checksums first,
+/// and each step runs in its own transaction together with its bookkeeping
+/// row, so a failed step leaves the schema at the previous version. Moving
+/// down requires a `down` script for every step.
+pub(crate) fn migrate_to<B: SqlBackend>(sql: &B, migrations: &[Migration], target: i32) -> Result<()> {
+    validate_migrations(migrations)?;
+    if target != 0 && !migrations.iter().any(|m| m.version == target) {
+        return Err(Error::RustError(format!("Unknown migration version: {}", target)));
+    }
+
+    ensure_migrations_table(sql)?;
+    let applied = applied_migrations(sql)?;
+    verify_migrations(sql, migrations, &applied)?;
+    let current = applied.last().map_or(0, |m| m.version);
+
+    if target >= current {
+        for migration in migrations.iter().filter(|m| m.version > current && m.version <= target) {
+            sql.transaction(|sql| {
+                sql.execute(migration.sql)?;
+                sql.prepare("INSERT INTO __migrations (version, name, checksum) VALUES (?, ?, ?)")
+                    .bind_value(migration.version)
+                    .bind_value(migration.name)
+                    .bind_value(migration.checksum())
+                    .run()?;
+                Ok(())
+            })?;
+        }
+    } else {
+        let steps: Vec<&Migration> = migrations.iter()
+            .rev()
+            .filter(|m| m.version > target && m.version <= current)
+            .collect();
+
+        // Refuse up front rather than stopping halfway down
+        if let Some(irreversible) = steps.iter().find(|m| m.down.is_none()) {
+            return Err(Error::RustError(format!(
+                "Migration {} ({}) has no down script and cannot be reverted",
+                irreversible.version, irreversible.name
+            )));
+        }
+
+        for migration in steps {
+            sql.transaction(|sql| {
+                sql.execute(migration.down.unwrap_or_default())?;
+                sql.prepare("DELETE FROM __migrations WHERE version = ?")
+                    .bind_value(migration.version)
+                    .run()?;
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Report every known migration and whether it has been applied
+pub(crate) fn migration_status<B: SqlBackend>(sql: &B, migrations: &[Migration]) -> Result<Vec<MigrationStatus>> {
+    ensure_migrations_table(sql)?;
+    let applied = applied_migrations(sql)?;
+
+    Ok(migrations.iter().map(|migration| {
+        let record = applied.iter().find(|a| a.version == migration.version);
+        let checksum = migration.checksum();
+        MigrationStatus {
+            version: migration.version,
+            name: migration.name,
+            applied: record.is_some(),
+            applied_at: record.and_then(|r| r.applied_at.clone()),
+            checksum_matches: record.and_then(|r| r.checksum.as_ref()).map(|c| *c == checksum),
+            checksum,
+            reversible: migration.down.is_some(),
+        }
+    }).collect())
+}
+
+/// Migrations must be listed in strictly increasing version order
+fn validate_migrations(migrations: &[Migration]) -> Result<()> {
+    if let Some(pair) = migrations.windows(2).find(|pair| pair[0].version >= pair[1].version) {
+        return Err(Error::RustError(format!(
+            "Migrations must be in increasing version order: {} is listed before {}",
+            pair[0].version, pair[1].version
+        )));
+    }
+    if migrations.first().is_some_and(|m| m.version <= 0) {
+        return Err(Error::RustError("Migration versions start at 1".into()));
+    }
+    Ok(())
+}
+
+fn ensure_migrations_table<B: SqlBackend>(sql: &B) -> Result<()> {
+    sql.execute(
+        "CREATE TABLE IF NOT EXISTS __migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )"
+    )?;
+
+    // Tables created before checksums were recorded lack the column
+    let has_checksum = sql
+        .execute("SELECT 1 FROM pragma_table_info('__migrations') WHERE name = 'checksum'")?
+        .next_row()?
+        .is_some();
+    if !has_checksum {
+        sql.execute("ALTER TABLE __migrations ADD COLUMN checksum TEXT")?;
+    }
+
+    Ok(())
+}
+
+fn applied_migrations<B: SqlBackend>(sql: &B) -> Result<Vec<AppliedMigration>> {
+    sql.execute("SELECT version, name, checksum, applied_at FROM __migrations ORDER BY version")?
+        .collect()
+}
+
+/// Check applied migrations against the known list. Rows recorded before
+/// checksums existed are trusted and have their checksum filled in.
+fn verify_migrations<B: SqlBackend>(sql: &B, migrations: &[Migration], applied: &[AppliedMigration]) -> Result<()> {
+    for record in applied {
+        let migration = migrations.iter()
+            .find(|m| m.version == record.version)
+            .ok_or_else(|| Error::RustError(format!(
+                "Applied migration {} ({}) is not in the migration list",
+                record.version, record.name
+            )))?;
+
+        let checksum = migration.checksum();
+        match &record.checksum {
+            Some(stored) if *stored != checksum => {
+                return Err(Error::RustError(format!(
+                    "Migration {} ({}) has changed since it was applied: checksum {} does not match stored {}",
+                    migration.version, migration.name, checksum, stored
+                )));
+            }
+            Some(_) => {}
+            None => {
+                sql.prepare("UPDATE __migrations SET checksum = ? WHERE version = ?")
+                    .bind_value(checksum)
+                    .bind_value(record.version)
+                    .run()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "create_a",
+            sql: "CREATE TABLE a (id INTEGER PRIMARY KEY);",
+            down: Some("DROP TABLE a;"),
+        },
+        Migration {
+            version: 2,
+            name: "create_b",
+            sql: "CREATE TABLE b (id INTEGER PRIMARY KEY);\nCREATE INDEX idx_b ON b (id);",
+            down: Some("DROP TABLE b;"),
+        },
+    ];
+
+    fn tables(sql: &NativeSqlite) -> Vec<String> {
+        sql.execute("SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ('a', 'b') ORDER BY name")
+            .unwrap()
+            .collect::<(String,)>()
+            .unwrap()
+            .into_iter()
+            .map(|(name,)| name)
+            .collect()
+    }
+
+    #[test]
+    fn migrates_up_down_and_reports_status() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.migrate(MIGRATIONS).unwrap();
+        assert_eq!(tables(&sql), ["a", "b"]);
+
+        // Applying again is a no-op
+        sql.migrate(MIGRATIONS).unwrap();
+
+        let status = sql.migration_status(MIGRATIONS).unwrap();
+        assert!(status.iter().all(|m| m.applied && m.checksum_matches == Some(true)));
+
+        sql.migrate_to(MIGRATIONS, 1).unwrap();
+        assert_eq!(tables(&sql), ["a"]);
+        let status = sql.migration_status(MIGRATIONS).unwrap();
+        assert_eq!(status.iter().map(|m| m.applied).collect::<Vec<_>>(), [true, false]);
+
+        sql.migrate_to(MIGRATIONS, 0).unwrap();
+        assert!(tables(&sql).is_empty());
+        assert!(sql.migrate_to(MIGRATIONS, 3).is_err());
+    }
+
+    #[test]
+    fn detects_edited_migrations() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.migrate(&MIGRATIONS[..1]).unwrap();
+
+        let edited = [Migration {
+            sql: "CREATE TABLE a (id INTEGER PRIMARY KEY, extra TEXT);",
+            ..MIGRATIONS[0]
+        }];
+        let error = sql.migrate(&edited).unwrap_err().to_string();
+        assert!(error.contains("has changed since it was applied"), "{}", error);
+    }
+
+    #[test]
+    fn failed_step_leaves_previous_version() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        let broken = [
+            Migration { ..MIGRATIONS[0] },
+            Migration {
+                version: 2,
+                name: "broken",
+                sql: "CREATE TABLE b (id INTEGER PRIMARY KEY); CREATE TABLE a (id INTEGER);",
+                down: None,
+            },
+        ];
+        assert!(sql.migrate(&broken).is_err());
+        assert_eq!(tables(&sql), ["a"]);
+
+        let status = sql.migration_status(&broken).unwrap();
+        assert_eq!(status.iter().map(|m| m.applied).collect::<Vec<_>>(), [true, false]);
+    }
+
+    #[test]
+    fn refuses_to_revert_irreversible_migrations() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        let irreversible = [Migration { down: None, ..MIGRATIONS[0] }];
+        sql.migrate(&irreversible).unwrap();
+        assert!(sql.migrate_to(&irreversible, 0).is_err());
+        assert_eq!(tables(&sql), ["a"]);
+    }
+
+    #[test]
+    fn rejects_unordered_migrations() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        let unordered = [Migration { version: 3, ..MIGRATIONS[0] }, Migration { version: 2, ..MIGRATIONS[1] }];
+        assert!(sql.migrate(&unordered).is_err());
+    }
+}
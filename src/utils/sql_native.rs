@@ -0,0 +1,174 @@
+use worker::Error;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{Batch, Connection, StatementStatus};
+use std::time::Instant;
+use crate::utils::sql_backend::{savepoint_transaction, Savepoints, SqlBackend, SqlCursor};
+use crate::utils::sql_row::SqlValue;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Embedded SQLite database for running SQL code outside the Workers runtime
+///
+/// Unlike a Durable Object, this backend does not stream. Statements run to
+/// completion when executed and every row is collected before the cursor is
+/// returned, so the cursor's row counters are final from the start and
+/// stopping early saves no work. Tests against it cover what queries return
+/// and count, not how a lazily stepped cursor behaves.
+pub struct NativeSqlite {
+    conn: Connection,
+}
+
+impl NativeSqlite {
+    /// Open a fresh in-memory database
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory().map_err(sql_error)?;
+        Ok(Self { conn })
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> Error {
+    Error::RustError(format!("SQL execution failed: {}", e))
+}
+
+impl SqlBackend for NativeSqlite {
+    type Cursor = NativeCursor;
+
+    fn exec_with(&self, query: &str, bindings: &[SqlValue]) -> Result<NativeCursor> {
+        let started = Instant::now();
+        let changes_before = self.conn.total_changes();
+        let mut columns = Vec::new();
+        let mut column_types = Vec::new();
+        let mut rows = Vec::new();
+        let mut rows_read = 0;
+        let mut remaining = bindings;
+
+        // Like exec, run every statement in the query and return the rows of
+        // the last one. Bindings are consumed by the statements in order.
+        let mut batch = Batch::new(&self.conn, query);
+        while let Some(mut statement) = batch.next().map_err(sql_error)? {
+            let count = statement.parameter_count();
+            if count > remaining.len() {
+                return Err(Error::RustError(format!(
+                    "Wrong number of parameter bindings: {} given",
+                    bindings.len()
+                )));
+            }
+            let (values, rest) = remaining.split_at(count);
+            remaining = rest;
+            for (index, value) in values.iter().enumerate() {
+                statement.raw_bind_parameter(index + 1, to_native(value)).map_err(sql_error)?;
+            }
+
+            columns = statement.column_names().into_iter().map(String::from).collect();
+            column_types = statement.columns().iter().map(|c| c.decl_type().map(String::from)).collect();
+            rows.clear();
+            let mut results = statement.raw_query();
+            while let Some(row) = results.next().map_err(sql_error)? {
+                let values = (0..columns.len())
+                    .map(|index| row.get_ref(index).map(from_native))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                    .map_err(sql_error)?;
+                rows.push(values);
+            }
+            drop(results);
+
+            // SQLite counts the rows stepped over by full table scans but not
+            // those reached through an index, which are at least the rows
+            // returned. The larger of the two approximates what a Durable
+            // Object reports as rows read.
+            let scanned = statement.get_status(StatementStatus::FullscanStep).max(0) as u64;
+            rows_read += scanned.max(rows.len() as u64);
+        }
+        if !remaining.is_empty() {
+            return Err(Error::RustError(format!(
+                "Wrong number of parameter bindings: {} given but only {} used",
+                bindings.len(),
+                bindings.len() - remaining.len()
+            )));
+        }
+
+        Ok(NativeCursor {
+            columns,
+            column_types,
+            rows: rows.into_iter(),
+            rows_read,
+            rows_written: self.conn.total_changes() - changes_before,
+            duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn last_changes(&self) -> Result<(u64, i64)> {
+        Ok((self.conn.changes(), self.conn.last_insert_rowid()))
+    }
+
+    fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Self) -> Result<R>,
+    {
+        savepoint_transaction(self, f)
+    }
+}
+
+impl Savepoints for NativeSqlite {}
+
+fn to_native(value: &SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(i) => Value::Integer(*i),
+        SqlValue::Real(f) => Value::Real(*f),
+        SqlValue::Text(s) => Value::Text(s.clone()),
+        SqlValue::Blob(bytes) => Value::Blob(bytes.clone()),
+    }
+}
+
+fn from_native(value: ValueRef<'_>) -> SqlValue {
+    match value {
+        ValueRef::Null => SqlValue::Null,
+        ValueRef::Integer(i) => SqlValue::Integer(i),
+        ValueRef::Real(f) => SqlValue::Real(f),
+        ValueRef::Text(s) => SqlValue::Text(String::from_utf8_lossy(s).into_owned()),
+        ValueRef::Blob(bytes) => SqlValue::Blob(bytes.to_vec()),
+    }
+}
+
+/// Cursor over a result set collected by `NativeSqlite`
+///
+/// `rows_read` is an estimate of the rows the statements scanned: the rows
+/// stepped over by full table scans, or the rows returned when that is more.
+/// Rows visited through an index, or skipped by SQLite's `count(*)`
+/// optimisation, are not counted, so it can be lower than the count a
+/// Durable Object reports for the same query.
+pub struct NativeCursor {
+    columns: Vec<String>,
+    column_types: Vec<Option<String>>,
+    rows: std::vec::IntoIter<Vec<SqlValue>>,
+    rows_read: u64,
+    rows_written: u64,
+    duration_ms: f64,
+}
+
+impl SqlCursor for NativeCursor {
+    fn column_names(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+    
+    fn column_types(&self) -> Vec<Option<String>> {
+        self.column_types.clone()
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<SqlValue>>> {
+        Ok(self.rows.next())
+    }
+
+    fn rows_read(&self) -> u64 {
+        self.rows_read
+    }
+
+    fn rows_written(&self) -> u64 {
+        self.rows_written
+    }
+
+    fn duration_ms(&self) -> Option<f64> {
+        Some(self.duration_ms)
+    }
+}
@@ -0,0 +1,438 @@
+use worker::Error;
+use crate::utils::sql_backend::{PreparedStatement, SqlBackend};
+use crate::utils::sql_row::{IntoSqlValue, SqlValue};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Sort direction for `order_by`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Comparison used by `where_cmp`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Cmp::Eq => "=",
+            Cmp::Ne => "<>",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        }
+    }
+}
+
+/// Convert `value` for binding to `target`, keeping the first failure in
+/// `error` so that `build` can report it
+fn convert(error: &mut Option<String>, target: &str, value: impl IntoSqlValue) -> SqlValue {
+    value.into_sql_value().unwrap_or_else(|e| {
+        error.get_or_insert_with(|| format!("Cannot bind {}: {}", target, e));
+        SqlValue::Null
+    })
+}
+
+/// Fail with the first value that could not be converted, if any
+fn check(error: Option<String>) -> Result<()> {
+    error.map_or(Ok(()), |error| Err(Error::RustError(error)))
+}
+
+/// Conditions joined with AND, with the values they bind in order
+#[derive(Debug, Clone, Default)]
+struct Filter {
+    conditions: Vec<String>,
+    values: Vec<SqlValue>,
+    error: Option<String>,
+}
+
+impl Filter {
+    fn push(&mut self, condition: String, values: impl IntoIterator<Item = SqlValue>) {
+        self.conditions.push(condition);
+        self.values.extend(values);
+    }
+
+    /// `column IN (subquery)` or `NOT IN`, keeping the subquery's error
+    fn push_in(&mut self, column: &str, op: &str, query: Select) {
+        match query.build() {
+            Ok((sql, values)) => self.push(format!("{} {} ({})", column, op, sql), values),
+            Err(error) => {
+                self.error.get_or_insert_with(|| error.to_string());
+            }
+        }
+    }
+
+    fn write(self, sql: &mut String, values: &mut Vec<SqlValue>) -> Result<()> {
+        check(self.error)?;
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+            values.extend(self.values);
+        }
+        Ok(())
+    }
+}
+
+/// `WHERE` methods shared by the statements that take conditions. Table and
+/// column names are `&'static str`, so they come from the code; every value
+/// is bound as a `?` parameter.
+macro_rules! filter_methods {
+    ($($builder:ident),*) => {
+        $(
+            impl $builder {
+                /// `column = ?`
+                pub fn where_eq(self, column: &'static str, value: impl IntoSqlValue) -> Self {
+                    self.where_cmp(column, Cmp::Eq, value)
+                }
+
+                /// `column <op> ?`
+                pub fn where_cmp(mut self, column: &'static str, cmp: Cmp, value: impl IntoSqlValue) -> Self {
+                    let value = convert(&mut self.filter.error, &format!("column `{}`", column), value);
+                    self.filter.push(format!("{} {} ?", column, cmp.as_sql()), [value]);
+                    self
+                }
+
+                pub fn where_null(mut self, column: &'static str) -> Self {
+                    self.filter.push(format!("{} IS NULL", column), []);
+                    self
+                }
+
+                pub fn where_not_null(mut self, column: &'static str) -> Self {
+                    self.filter.push(format!("{} IS NOT NULL", column), []);
+                    self
+                }
+
+                /// `column IN (subquery)`
+                pub fn where_in(mut self, column: &'static str, query: Select) -> Self {
+                    self.filter.push_in(column, "IN", query);
+                    self
+                }
+
+                /// `column NOT IN (subquery)`
+                pub fn where_not_in(mut self, column: &'static str, query: Select) -> Self {
+                    self.filter.push_in(column, "NOT IN", query);
+                    self
+                }
+
+                /// A condition written in SQL, with a `?` placeholder for each
+                /// of `values`
+                pub fn where_sql<T: IntoSqlValue>(mut self, condition: &str, values: impl IntoIterator<Item = T>) -> Self {
+                    let target = format!("a value of `{}`", condition);
+                    let values: Vec<SqlValue> = values.into_iter().map(|v| convert(&mut self.filter.error, &target, v)).collect();
+                    self.filter.push(format!("({})", condition), values);
+                    self
+                }
+            }
+        )*
+    };
+}
+
+/// Statement methods shared by every builder
+macro_rules! statement_methods {
+    ($($builder:ident),*) => {
+        $(
+            impl $builder {
+                /// Prepare the statement on `sql` with its values bound. A value
+                /// that could not be converted fails the statement when it runs.
+                pub fn prepare<B: SqlBackend>(self, sql: &B) -> PreparedStatement<'_, B> {
+                    match self.build() {
+                        Ok((query, values)) => sql.prepare(&query).bind(values),
+                        Err(error) => PreparedStatement::failed(sql, error),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+/// `SELECT columns`; no columns selects `*`
+pub fn select(columns: &[&'static str]) -> Select {
+    Select {
+        columns: columns.to_vec(),
+        table: "",
+        filter: Filter::default(),
+        order: Vec::new(),
+        limit: None,
+        offset: None,
+    }
+}
+
+/// `INSERT INTO table`
+pub fn insert_into(table: &'static str) -> Insert {
+    Insert {
+        table,
+        columns: Vec::new(),
+        values: Vec::new(),
+        returning: Vec::new(),
+        error: None,
+    }
+}
+
+/// `UPDATE table`
+pub fn update(table: &'static str) -> Update {
+    Update {
+        table,
+        set: Vec::new(),
+        values: Vec::new(),
+        filter: Filter::default(),
+        returning: Vec::new(),
+        error: None,
+    }
+}
+
+/// `DELETE FROM table`
+pub fn delete_from(table: &'static str) -> Delete {
+    Delete {
+        table,
+        filter: Filter::default(),
+        returning: Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Select {
+    columns: Vec<&'static str>,
+    table: &'static str,
+    filter: Filter,
+    order: Vec<(&'static str, Order)>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl Select {
+    pub fn from(mut self, table: &'static str) -> Self {
+        self.table = table;
+        self
+    }
+
+    /// Add a sort key; keys apply in the order they are added
+    pub fn order_by(mut self, column: &'static str, order: Order) -> Self {
+        self.order.push((column, order));
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// SQL text and the values for its placeholders, in order, or the first
+    /// value that could not be converted
+    pub fn build(self) -> Result<(String, Vec<SqlValue>)> {
+        let columns = if self.columns.is_empty() { "*".to_string() } else { self.columns.join(", ") };
+        let mut sql = format!("SELECT {} FROM {}", columns, self.table);
+        let mut values = Vec::new();
+        self.filter.write(&mut sql, &mut values)?;
+
+        if !self.order.is_empty() {
+            let keys: Vec<String> = self.order.iter()
+                .map(|(column, order)| format!("{} {}", column, match order {
+                    Order::Asc => "ASC",
+                    Order::Desc => "DESC",
+                }))
+                .collect();
+            sql.push_str(" ORDER BY ");
+            sql.push_str(&keys.join(", "));
+        }
+        // SQLite only accepts OFFSET after a LIMIT; -1 means no limit
+        if self.limit.is_some() || self.offset.is_some() {
+            sql.push_str(" LIMIT ?");
+            values.push(SqlValue::Integer(self.limit.unwrap_or(-1)));
+        }
+        if let Some(offset) = self.offset {
+            sql.push_str(" OFFSET ?");
+            values.push(SqlValue::Integer(offset));
+        }
+        Ok((sql, values))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Insert {
+    table: &'static str,
+    columns: Vec<&'static str>,
+    values: Vec<SqlValue>,
+    returning: Vec<&'static str>,
+    error: Option<String>,
+}
+
+impl Insert {
+    pub fn value(mut self, column: &'static str, value: impl IntoSqlValue) -> Self {
+        self.columns.push(column);
+        let value = convert(&mut self.error, &format!("column `{}`", column), value);
+        self.values.push(value);
+        self
+    }
+
+    pub fn values<T: IntoSqlValue>(self, values: impl IntoIterator<Item = (&'static str, T)>) -> Self {
+        values.into_iter().fold(self, |insert, (column, value)| insert.value(column, value))
+    }
+
+    pub fn returning(mut self, columns: &[&'static str]) -> Self {
+        self.returning = columns.to_vec();
+        self
+    }
+
+    /// SQL text and the values for its placeholders, in order, or the first
+    /// value that could not be converted
+    pub fn build(self) -> Result<(String, Vec<SqlValue>)> {
+        check(self.error)?;
+        let placeholders = vec!["?"; self.columns.len()].join(", ");
+        let mut sql = format!("INSERT INTO {} ({}) VALUES ({})", self.table, self.columns.join(", "), placeholders);
+        push_returning(&mut sql, &self.returning);
+        Ok((sql, self.values))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Update {
+    table: &'static str,
+    set: Vec<&'static str>,
+    values: Vec<SqlValue>,
+    filter: Filter,
+    returning: Vec<&'static str>,
+    error: Option<String>,
+}
+
+impl Update {
+    pub fn set(mut self, column: &'static str, value: impl IntoSqlValue) -> Self {
+        self.set.push(column);
+        let value = convert(&mut self.error, &format!("column `{}`", column), value);
+        self.values.push(value);
+        self
+    }
+
+    pub fn returning(mut self, columns: &[&'static str]) -> Self {
+        self.returning = columns.to_vec();
+        self
+    }
+
+    /// SQL text and the values for its placeholders, in order, or the first
+    /// value that could not be converted
+    pub fn build(self) -> Result<(String, Vec<SqlValue>)> {
+        check(self.error)?;
+        let set: Vec<String> = self.set.iter().map(|column| format!("{} = ?", column)).collect();
+        let mut sql = format!("UPDATE {} SET {}", self.table, set.join(", "));
+        let mut values = self.values;
+        self.filter.write(&mut sql, &mut values)?;
+        push_returning(&mut sql, &self.returning);
+        Ok((sql, values))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Delete {
+    table: &'static str,
+    filter: Filter,
+    returning: Vec<&'static str>,
+}
+
+impl Delete {
+    pub fn returning(mut self, columns: &[&'static str]) -> Self {
+        self.returning = columns.to_vec();
+        self
+    }
+
+    /// SQL text and the values for its placeholders, in order, or the first
+    /// value that could not be converted
+    pub fn build(self) -> Result<(String, Vec<SqlValue>)> {
+        let mut sql = format!("DELETE FROM {}", self.table);
+        let mut values = Vec::new();
+        self.filter.write(&mut sql, &mut values)?;
+        push_returning(&mut sql, &self.returning);
+        Ok((sql, values))
+    }
+}
+
+fn push_returning(sql: &mut String, columns: &[&'static str]) {
+    if !columns.is_empty() {
+        sql.push_str(" RETURNING ");
+        sql.push_str(&columns.join(", "));
+    }
+}
+
+filter_methods!(Select, Update, Delete);
+statement_methods!(Select, Insert, Update, Delete);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    #[test]
+    fn builds_statements_with_bound_values() {
+        let (sql, values) = select(&["id", "name"])
+            .from("items")
+            .where_eq("name", "it's")
+            .where_cmp("qty", Cmp::Ge, 2)
+            .where_null("deleted_at")
+            .where_sql("(qty, id) < (?, ?)", [10, 3])
+            .order_by("qty", Order::Desc)
+            .order_by("id", Order::Asc)
+            .limit(5)
+            .build()
+            .unwrap();
+        assert_eq!(sql, "SELECT id, name FROM items WHERE name = ? AND qty >= ? AND deleted_at IS NULL AND ((qty, id) < (?, ?)) ORDER BY qty DESC, id ASC LIMIT ?");
+        assert_eq!(values, [
+            SqlValue::Text("it's".into()),
+            SqlValue::Integer(2),
+            SqlValue::Integer(10),
+            SqlValue::Integer(3),
+            SqlValue::Integer(5),
+        ]);
+
+        assert_eq!(select(&[]).from("items").offset(10).build().unwrap().0, "SELECT * FROM items LIMIT ? OFFSET ?");
+        assert_eq!(
+            update("items").set("qty", 1).set("name", None::<String>).where_eq("id", 4).returning(&["id"]).build().unwrap(),
+            ("UPDATE items SET qty = ?, name = ? WHERE id = ? RETURNING id".into(), vec![SqlValue::Integer(1), SqlValue::Null, SqlValue::Integer(4)]),
+        );
+        assert_eq!(
+            delete_from("items").where_in("id", select(&["id"]).from("items").where_cmp("qty", Cmp::Lt, 0).limit(2)).build().unwrap(),
+            ("DELETE FROM items WHERE id IN (SELECT id FROM items WHERE qty < ? LIMIT ?)".into(), vec![SqlValue::Integer(0), SqlValue::Integer(2)]),
+        );
+    }
+
+    #[test]
+    fn runs_built_statements() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, qty INTEGER)").unwrap();
+
+        for (name, qty) in [("a", 1), ("b'; DROP TABLE items; --", 2), ("c", 3)] {
+            insert_into("items").value("name", name).value("qty", qty).prepare(&sql).run().unwrap();
+        }
+        let (id,) = insert_into("items").values([("name", "d")]).returning(&["id"]).prepare(&sql).first::<(i64,)>().unwrap().unwrap();
+        assert_eq!(id, 4);
+
+        update("items").set("qty", 0).where_cmp("id", Cmp::Gt, 2).prepare(&sql).run().unwrap();
+        let oldest = select(&["id"]).from("items").order_by("id", Order::Asc).limit(1);
+        delete_from("items").where_in("id", oldest).prepare(&sql).run().unwrap();
+
+        let rows = select(&["name", "qty"]).from("items").where_not_null("qty").order_by("id", Order::Asc)
+            .prepare(&sql)
+            .all::<(String, i64)>()
+            .unwrap();
+        assert_eq!(rows, [("b'; DROP TABLE items; --".into(), 2), ("c".into(), 0), ("d".into(), 0)]);
+
+        let error = insert_into("items").value("name", "e").value("qty", u64::MAX).prepare(&sql).run().unwrap_err();
+        assert!(error.to_string().contains("column `qty`"), "{}", error);
+        let subquery = select(&["id"]).from("items").where_cmp("qty", Cmp::Lt, u64::MAX);
+        assert!(delete_from("items").where_in("id", subquery).prepare(&sql).run().is_err());
+        assert_eq!(select(&[]).from("items").prepare(&sql).all::<(i64, String, i64)>().unwrap().len(), 3);
+    }
+}
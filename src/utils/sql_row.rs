@@ -0,0 +1,558 @@
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+use std::rc::Rc;
+use worker::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A single SQLite value as returned by a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqlValue {
+    /// Classify a JS number. SQLite integers arrive as doubles, so any whole
+    /// number within the safe integer range is treated as an INTEGER.
+    pub fn from_f64(n: f64) -> Self {
+        const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+        if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER {
+            SqlValue::Integer(n as i64)
+        } else {
+            SqlValue::Real(n)
+        }
+    }
+
+    /// Name of the SQLite storage class, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SqlValue::Null => "NULL",
+            SqlValue::Integer(_) => "INTEGER",
+            SqlValue::Real(_) => "REAL",
+            SqlValue::Text(_) => "TEXT",
+            SqlValue::Blob(_) => "BLOB",
+        }
+    }
+}
+
+/// One result row: values in column order, addressable by column name
+#[derive(Debug, Clone)]
+pub struct Row {
+    columns: Rc<[String]>,
+    values: Vec<SqlValue>,
+}
+
+impl Row {
+    pub fn new(columns: Rc<[String]>, values: Vec<SqlValue>) -> Self {
+        Self { columns, values }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[SqlValue] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<SqlValue> {
+        self.values
+    }
+
+    /// Raw value of a column by name
+    pub fn value(&self, column: &str) -> Option<&SqlValue> {
+        self.columns.iter()
+            .position(|c| c == column)
+            .and_then(|i| self.values.get(i))
+    }
+
+    /// Convert a single column by name, using the same rules as `FromRow`
+    pub fn get<T: DeserializeOwned>(&self, column: &str) -> Result<T> {
+        let value = self.value(column)
+            .ok_or_else(|| Error::RustError(format!("No such column: {}", column)))?;
+        T::deserialize(ValueDeserializer(value))
+            .map_err(|e| Error::RustError(format!("Failed to map column `{}`: {}", column, e)))
+    }
+}
+
+/// Conversion from a result row into a Rust type
+///
+/// Every `serde::Deserialize` type gets an implementation that maps struct
+/// fields by column name and tuples by column position. SQLite type affinity
+/// is smoothed over: whole-number REALs convert to integers, INTEGER 0/1
+/// converts to `bool`, and NULL converts to `None`. Conversion errors name
+/// the offending column.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+impl<T: DeserializeOwned> FromRow for T {
+    fn from_row(row: &Row) -> Result<Self> {
+        T::deserialize(RowDeserializer(row))
+            .map_err(|e| Error::RustError(format!("Failed to map row: {}", e)))
+    }
+}
+
+impl FromRow for Row {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(row.clone())
+    }
+}
+
+/// Error raised while mapping a row
+#[derive(Debug)]
+pub struct RowError(String);
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RowError {}
+
+impl de::Error for RowError {
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        RowError(msg.to_string())
+    }
+}
+
+struct RowDeserializer<'a>(&'a Row);
+
+impl<'de, 'a> de::Deserializer<'de> for RowDeserializer<'a> {
+    type Error = RowError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        visitor.visit_map(RowMap { row: self.0, index: 0 })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        visitor.visit_seq(RowSeq { row: self.0, index: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> std::result::Result<V::Value, RowError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct map struct enum identifier ignored_any
+    }
+}
+
+struct RowMap<'a> {
+    row: &'a Row,
+    index: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for RowMap<'a> {
+    type Error = RowError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> std::result::Result<Option<K::Value>, RowError> {
+        match self.row.columns.get(self.index) {
+            Some(column) => seed.deserialize(column.as_str().into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> std::result::Result<V::Value, RowError> {
+        let index = self.index;
+        self.index += 1;
+        let column = &self.row.columns[index];
+        let value = self.row.values.get(index).unwrap_or(&SqlValue::Null);
+        seed.deserialize(ValueDeserializer(value))
+            .map_err(|e| RowError(format!("column `{}`: {}", column, e)))
+    }
+}
+
+struct RowSeq<'a> {
+    row: &'a Row,
+    index: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for RowSeq<'a> {
+    type Error = RowError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> std::result::Result<Option<T::Value>, RowError> {
+        let index = self.index;
+        let Some(value) = self.row.values.get(index) else {
+            return Ok(None);
+        };
+        self.index += 1;
+        let column = self.row.columns.get(index).map(String::as_str).unwrap_or("?");
+        seed.deserialize(ValueDeserializer(value))
+            .map(Some)
+            .map_err(|e| RowError(format!("column `{}` (#{}): {}", column, index + 1, e)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.values.len() - self.index)
+    }
+}
+
+struct ValueDeserializer<'a>(&'a SqlValue);
+
+impl<'a> ValueDeserializer<'a> {
+    fn invalid(&self, expected: &str) -> RowError {
+        RowError(format!("expected {}, found {}", expected, self.0.type_name()))
+    }
+
+    /// Integer view of the value, accepting whole-number REALs
+    fn as_i64(&self) -> Option<i64> {
+        match *self.0 {
+            SqlValue::Integer(i) => Some(i),
+            SqlValue::Real(f) if f.fract() == 0.0 && f >= i64::MIN as f64 && f <= i64::MAX as f64 => Some(f as i64),
+            _ => None,
+        }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = RowError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Null => visitor.visit_unit(),
+            SqlValue::Integer(i) => visitor.visit_i64(*i),
+            SqlValue::Real(f) => visitor.visit_f64(*f),
+            SqlValue::Text(s) => visitor.visit_str(s),
+            SqlValue::Blob(b) => visitor.visit_bytes(b),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.as_i64() {
+            Some(0) => visitor.visit_bool(false),
+            Some(1) => visitor.visit_bool(true),
+            _ => Err(self.invalid("boolean (0 or 1)")),
+        }
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.as_i64() {
+            Some(i) => visitor.visit_i64(i),
+            None => Err(self.invalid("integer")),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_i64(visitor)
+    }
+
+    /// Negative integers are refused, as for the narrower unsigned types,
+    /// rather than read as the `u64` with the same bits
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.as_i64() {
+            Some(i) => match u64::try_from(i) {
+                Ok(u) => visitor.visit_u64(u),
+                Err(_) => Err(RowError(format!("{} is out of range for u64", i))),
+            },
+            None => Err(self.invalid("integer")),
+        }
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match *self.0 {
+            SqlValue::Integer(i) => visitor.visit_f64(i as f64),
+            SqlValue::Real(f) => visitor.visit_f64(f),
+            _ => Err(self.invalid("number")),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Text(s) => visitor.visit_str(s),
+            _ => Err(self.invalid("text")),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Blob(b) => visitor.visit_bytes(b),
+            SqlValue::Text(s) => visitor.visit_bytes(s.as_bytes()),
+            _ => Err(self.invalid("blob")),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Blob(b) => visitor.visit_seq(de::value::SeqDeserializer::new(b.iter().copied())),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Null => visitor.visit_unit(),
+            _ => Err(self.invalid("NULL")),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> std::result::Result<V::Value, RowError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, RowError> {
+        match self.0 {
+            SqlValue::Text(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            _ => Err(self.invalid("text enum variant")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Conversion of Rust values into SQLite values for parameter binding.
+/// Fails for values SQLite cannot store as they are; the binder names the
+/// column or parameter in the error.
+pub trait IntoSqlValue {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String>;
+}
+
+impl IntoSqlValue for SqlValue {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(self)
+    }
+}
+
+impl IntoSqlValue for &str {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Text(self.to_string()))
+    }
+}
+
+impl IntoSqlValue for String {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Text(self))
+    }
+}
+
+impl IntoSqlValue for &String {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Text(self.clone()))
+    }
+}
+
+impl IntoSqlValue for i32 {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Integer(self as i64))
+    }
+}
+
+impl IntoSqlValue for i64 {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Integer(self))
+    }
+}
+
+/// SQLite integers are signed, so values above `i64::MAX` are refused
+/// rather than stored as negative numbers that would sort and compare wrongly
+impl IntoSqlValue for u64 {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        i64::try_from(self)
+            .map(SqlValue::Integer)
+            .map_err(|_| format!("{} is out of range for an SQLite integer", self))
+    }
+}
+
+impl IntoSqlValue for f64 {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Real(self))
+    }
+}
+
+impl IntoSqlValue for bool {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Integer(self as i64))
+    }
+}
+
+impl IntoSqlValue for Vec<u8> {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Blob(self))
+    }
+}
+
+impl IntoSqlValue for &[u8] {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        Ok(SqlValue::Blob(self.to_vec()))
+    }
+}
+
+impl<T: IntoSqlValue> IntoSqlValue for Option<T> {
+    fn into_sql_value(self) -> std::result::Result<SqlValue, String> {
+        match self {
+            Some(value) => value.into_sql_value(),
+            None => Ok(SqlValue::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn row(columns: &[&str], values: Vec<SqlValue>) -> Row {
+        Row::new(columns.iter().map(|c| c.to_string()).collect(), values)
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Message {
+        id: i64,
+        content: String,
+        deleted: bool,
+        edited_at: Option<i64>,
+        score: f64,
+    }
+
+    #[test]
+    fn maps_struct_fields_by_column_name() {
+        let row = row(
+            &["score", "deleted", "content", "edited_at", "id"],
+            vec![SqlValue::Integer(3), SqlValue::Integer(1), SqlValue::Text("hi".into()), SqlValue::Null, SqlValue::Integer(7)],
+        );
+        let message = Message::from_row(&row).unwrap();
+        assert_eq!(message, Message { id: 7, content: "hi".into(), deleted: true, edited_at: None, score: 3.0 });
+        assert_eq!(<(f64, bool)>::from_row(&row).unwrap(), (3.0, true));
+    }
+
+    #[test]
+    fn converts_whole_number_reals_to_integers() {
+        let row = row(&["n", "half"], vec![SqlValue::Real(42.0), SqlValue::Real(1.5)]);
+        assert_eq!(row.get::<i64>("n").unwrap(), 42);
+        assert_eq!(row.get::<u32>("n").unwrap(), 42);
+        assert!(row.get::<i64>("half").is_err());
+        assert_eq!(row.get::<f64>("half").unwrap(), 1.5);
+    }
+
+    #[test]
+    fn converts_zero_and_one_to_bool() {
+        let row = row(&["no", "yes", "real", "two", "text"], vec![
+            SqlValue::Integer(0),
+            SqlValue::Integer(1),
+            SqlValue::Real(1.0),
+            SqlValue::Integer(2),
+            SqlValue::Text("true".into()),
+        ]);
+        assert!(!row.get::<bool>("no").unwrap());
+        assert!(row.get::<bool>("yes").unwrap());
+        assert!(row.get::<bool>("real").unwrap());
+        assert!(row.get::<bool>("two").is_err());
+        assert!(row.get::<bool>("text").is_err());
+    }
+
+    #[test]
+    fn converts_null_to_none() {
+        let row = row(&["missing", "present"], vec![SqlValue::Null, SqlValue::Text("x".into())]);
+        assert_eq!(row.get::<Option<String>>("missing").unwrap(), None);
+        assert_eq!(row.get::<Option<String>>("present").unwrap(), Some("x".into()));
+        assert!(row.get::<String>("missing").is_err());
+    }
+
+    #[test]
+    fn errors_name_the_column() {
+        let bad = row(
+            &["id", "content", "deleted", "edited_at", "score"],
+            vec![SqlValue::Integer(1), SqlValue::Integer(5), SqlValue::Integer(0), SqlValue::Null, SqlValue::Real(0.5)],
+        );
+        let error = Message::from_row(&bad).unwrap_err().to_string();
+        assert!(error.contains("column `content`") && error.contains("expected text, found INTEGER"), "{}", error);
+
+        let error = <(i64, String)>::from_row(&bad).unwrap_err().to_string();
+        assert!(error.contains("column `content` (#2)"), "{}", error);
+
+        let error = bad.get::<i64>("score").unwrap_err().to_string();
+        assert!(error.contains("`score`"), "{}", error);
+        assert!(bad.get::<i64>("nope").unwrap_err().to_string().contains("No such column: nope"));
+    }
+
+    #[test]
+    fn integers_are_range_checked_both_ways() {
+        let row = row(&["n", "max"], vec![SqlValue::Integer(-1), SqlValue::Integer(i64::MAX)]);
+        assert!(row.get::<u32>("n").is_err());
+        assert!(row.get::<i8>("n").is_ok());
+        let error = row.get::<u64>("n").unwrap_err().to_string();
+        assert!(error.contains("`n`") && error.contains("-1 is out of range"), "{}", error);
+        assert_eq!(row.get::<u64>("max").unwrap(), i64::MAX as u64);
+
+        assert_eq!((i64::MAX as u64).into_sql_value(), Ok(SqlValue::Integer(i64::MAX)));
+        assert!(u64::MAX.into_sql_value().unwrap_err().contains("out of range"));
+    }
+}
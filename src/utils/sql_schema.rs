@@ -0,0 +1,210 @@
+use worker::Error;
+use serde::{Deserialize, Serialize};
+use crate::utils::sql_backend::{SqlBackend, SqlCursor};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Live schema of a database, as returned by `SqlBackend::schema`
+#[derive(Serialize, Debug)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+}
+
+impl Schema {
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Table {
+    pub name: String,
+    /// The `CREATE TABLE` statement
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+/// A row of `pragma_table_info`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Column {
+    pub cid: i64,
+    pub name: String,
+    /// Declared type, empty when the column has none
+    #[serde(rename = "type")]
+    pub declared_type: String,
+    #[serde(rename = "notnull")]
+    pub not_null: bool,
+    #[serde(rename = "dflt_value")]
+    pub default_value: Option<String>,
+    /// 1-based position in the primary key, or 0
+    #[serde(rename = "pk")]
+    pub primary_key: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Index {
+    pub name: String,
+    pub unique: bool,
+    /// `c` for CREATE INDEX, `u` for a UNIQUE constraint, `pk` for the primary key
+    pub origin: String,
+    pub partial: bool,
+    pub columns: Vec<String>,
+    /// The `CREATE INDEX` statement, absent for automatic indexes
+    pub sql: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ForeignKey {
+    pub table: String,
+    pub from: Vec<String>,
+    pub to: Vec<Option<String>>,
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+/// Read tables, columns, indexes and foreign keys from the catalog
+pub(crate) fn schema<B: SqlBackend>(sql: &B) -> Result<Schema> {
+    #[derive(Deserialize)]
+    struct MasterRow {
+        #[serde(rename = "type")]
+        kind: String,
+        name: String,
+        sql: Option<String>,
+    }
+
+    let master = sql.execute(include_str!("../sql/get_schema.sql"))?.collect::<MasterRow>()?;
+    let index_sql = |name: &str| {
+        master.iter()
+            .find(|row| row.kind == "index" && row.name == name)
+            .and_then(|row| row.sql.clone())
+    };
+
+    let mut tables = Vec::new();
+    for row in master.iter().filter(|row| row.kind == "table") {
+        tables.push(Table {
+            columns: sql.prepare("SELECT cid, name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid")
+                .bind_value(row.name.as_str())
+                .all::<Column>()?,
+            indexes: indexes(sql, &row.name)?
+                .into_iter()
+                .map(|index| Index { sql: index_sql(&index.name), ..index })
+                .collect(),
+            foreign_keys: foreign_keys(sql, &row.name)?,
+            name: row.name.clone(),
+            sql: row.sql.clone(),
+        });
+    }
+
+    Ok(Schema { tables })
+}
+
+fn indexes<B: SqlBackend>(sql: &B, table: &str) -> Result<Vec<Index>> {
+    #[derive(Deserialize)]
+    struct IndexRow {
+        name: String,
+        unique: bool,
+        origin: String,
+        partial: bool,
+    }
+
+    let rows = sql.prepare("SELECT name, \"unique\", origin, partial FROM pragma_index_list(?) ORDER BY name")
+        .bind_value(table)
+        .all::<IndexRow>()?;
+
+    rows.into_iter().map(|row| {
+        let columns = sql.prepare("SELECT name FROM pragma_index_info(?) ORDER BY seqno")
+            .bind_value(row.name.as_str())
+            .all::<(Option<String>,)>()?
+            .into_iter()
+            // Expression columns have no name
+            .map(|(name,)| name.unwrap_or_else(|| "<expr>".to_string()))
+            .collect();
+        Ok(Index {
+            name: row.name,
+            unique: row.unique,
+            origin: row.origin,
+            partial: row.partial,
+            columns,
+            sql: None,
+        })
+    }).collect()
+}
+
+fn foreign_keys<B: SqlBackend>(sql: &B, table: &str) -> Result<Vec<ForeignKey>> {
+    #[derive(Deserialize)]
+    struct ForeignKeyRow {
+        id: i64,
+        table: String,
+        from: String,
+        to: Option<String>,
+        on_update: String,
+        on_delete: String,
+    }
+
+    let rows = sql.prepare("SELECT id, \"table\", \"from\", \"to\", on_update, on_delete FROM pragma_foreign_key_list(?) ORDER BY id, seq")
+        .bind_value(table)
+        .all::<ForeignKeyRow>()?;
+
+    // Composite keys span several rows sharing an id
+    let mut keys: Vec<(i64, ForeignKey)> = Vec::new();
+    for row in rows {
+        match keys.last_mut() {
+            Some((id, key)) if *id == row.id => {
+                key.from.push(row.from);
+                key.to.push(row.to);
+            }
+            _ => keys.push((row.id, ForeignKey {
+                table: row.table,
+                from: vec![row.from],
+                to: vec![row.to],
+                on_update: row.on_update,
+                on_delete: row.on_delete,
+            })),
+        }
+    }
+
+    Ok(keys.into_iter().map(|(_, key)| key).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sql_native::NativeSqlite;
+
+    #[test]
+    fn reads_columns_indexes_and_foreign_keys() {
+        let sql = NativeSqlite::open_in_memory().unwrap();
+        sql.execute(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL UNIQUE);
+             CREATE TABLE posts (
+                 id INTEGER PRIMARY KEY,
+                 author_id INTEGER REFERENCES users (id) ON DELETE CASCADE,
+                 title TEXT DEFAULT 'untitled'
+             );
+             CREATE INDEX idx_posts_author ON posts (author_id, title);"
+        ).unwrap();
+
+        let schema = sql.schema().unwrap();
+        assert_eq!(schema.tables.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["posts", "users"]);
+
+        let posts = schema.table("posts").unwrap();
+        let title = posts.columns.iter().find(|c| c.name == "title").unwrap();
+        assert_eq!(title.declared_type, "TEXT");
+        assert_eq!(title.default_value.as_deref(), Some("'untitled'"));
+        assert_eq!(posts.columns[0].primary_key, 1);
+
+        let index = &posts.indexes[0];
+        assert_eq!(index.columns, ["author_id", "title"]);
+        assert!(index.sql.as_deref().unwrap().starts_with("CREATE INDEX idx_posts_author"));
+
+        let key = &posts.foreign_keys[0];
+        assert_eq!((key.table.as_str(), key.on_delete.as_str()), ("users", "CASCADE"));
+        assert_eq!(key.from, ["author_id"]);
+
+        let users = schema.table("users").unwrap();
+        assert!(users.columns.iter().any(|c| c.name == "email" && c.not_null));
+        assert!(users.indexes.iter().any(|i| i.unique && i.origin == "u" && i.sql.is_none()));
+    }
+}